@@ -2,11 +2,19 @@ import subprocess
 import json
 import time
 
+def move_from_json(move):
+    # Moves as tuples (startX, startY, endX, endY) with the promotion as fifth
+    # element, if there is one
+    if "promotion" in move:
+        return (move["from"][0], move["from"][1], move["to"][0], move["to"][1], move["promotion"])
+    return (move["from"][0], move["from"][1], move["to"][0], move["to"][1])
+
 class RustAI:
     def __init__(self, color, look_ahead):
         self.color = color
         color_string = "white" if color == 1 else "black"
         self.max_look_ahead = look_ahead
+        self.status = "Ongoing"
         self.process = subprocess.Popen(["../target/release/chess-ai", color_string, str(look_ahead)],
             stdin=subprocess.PIPE, stdout=subprocess.PIPE, encoding="utf8")
 
@@ -16,19 +24,32 @@ class RustAI:
         return self.get_best_score(field, self.max_look_ahead)
 
     def get_best_score(self, field, look_ahead): # Return's the value of the highest possible score
-        # Write state of the field to the subprocess' stdin
-        self.process.stdin.write(field.toJson() + "\n")
+        # Write the game to the subprocess' stdin. With all moves, the engine
+        # knows about repetitions, castling rights and en passant
+        self.process.stdin.write(json.dumps(field.game_request()) + "\n")
         self.process.stdin.flush()
         # Read out the calculated move from stdout
         output = self.process.stdout.readline()
         try:
-            move = json.loads(output)
+            response = json.loads(output)
         except:
             print("AI errored")
             time.sleep(1000)
+        # The status of the game after the move, e.g. "Ongoing" or {"Checkmate": {"winner": "White"}}
+        self.status = response["status"]
+        move = response["move"]
         if move is None:
             return None
-        return (move["from"][0], move["from"][1], move["to"][0], move["to"][1])
+        return move_from_json(move)
+
+    def legal_moves(self, field):
+        # The engine knows all rules, e.g. castling, en passant and pins, so
+        # ask it instead of checking them again
+        request = dict(field.game_request(), cmd="moves")
+        self.process.stdin.write(json.dumps(request) + "\n")
+        self.process.stdin.flush()
+        response = json.loads(self.process.stdout.readline())
+        return [move_from_json(move) for move in response["moves"]]
 
 from copy import deepcopy
 import random
@@ -1,12 +1,12 @@
 import pygame
 import random
 from math import floor
-import copy
 from chesspieces import *
 from ai import *
 import time
 from matplotlib import pyplot as plt
-import json
+
+START_FEN = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
 
 def main():
     selected = None
@@ -25,6 +25,9 @@ def main():
     #player1 = AI(1, 2)
     #player2 = -1
     currentPlayer = player1
+    # Knows the legal moves of the human player as well
+    rules = player2
+    legal_moves = [] # The legal moves of the selected piece
 
     timeTaken = []
 
@@ -52,22 +55,13 @@ def main():
             if clicked is not None and clicked.color is currentPlayer:
                 # The player selected one of his peces
                 selected = mouse.getPos()
-                reachables = field.get(selected[0], selected[1]).check_reachables(field, selected[0], selected[1])
-                # Check if any of the moves in [reachables] will result in a Checkmate
-                reachables_copy = copy.deepcopy(reachables)
-                reachables = []
-                # Loop through [reachables_copy] and add all viable moves(not in check moves) to [reachables]
-                for i, reachable in enumerate(reachables_copy):
-                    move = (selected[0], selected[1], reachable [0], reachable[1])
-                    think_field = copy.deepcopy(field).move(move)
-                    if think_field.is_checkmate(currentPlayer):
-                        # This move results in a check, so don't add
-                        pass
-                    else:
-                        reachables.append(reachable)
+                legal_moves = [move for move in rules.legal_moves(field) if move[:2] == selected]
+                reachables = [move[2:4] for move in legal_moves]
             elif selected is not None and mouse.getPos() in reachables:
-                # The player clicked on a reachable field, so move that piece
-                field.move((selected[0], selected[1], mouse.getPos()[0], mouse.getPos()[1]))
+                # The player clicked on a reachable field, so move that piece.
+                # Pawns reaching the last row become queens
+                moves = [move for move in legal_moves if move[2:4] == mouse.getPos()]
+                field.move(next((move for move in moves if move[4:] == ("Queen",)), moves[0]))
                 selected = None
                 reachables = []
                 last_move = ()
@@ -89,14 +83,17 @@ def main():
             if last_move is None:
                 print("----------------------")
                 print("Gameover: %s cannot move" % ("White" if currentPlayer.color == 1 else "Black"))
+                if isinstance(currentPlayer, RustAI):
+                    print("Result:", currentPlayer.status)
                 time.sleep(1000)
-            start = field.get(last_move[0], last_move[1])
             # Validate the ai's move
-            if start is None or not (last_move[2], last_move[3]) in\
-                start.check_reachables(field, last_move[0], last_move[1]):
-                print("Invalid move by AI:", start, last_move)
+            if isinstance(currentPlayer, PythonAI) and last_move not in rules.legal_moves(field):
+                print("Invalid move by AI:", field.get(last_move[0], last_move[1]), last_move)
 
             field.move(last_move)
+            if isinstance(currentPlayer, RustAI) and currentPlayer.status != "Ongoing":
+                print("----------------------")
+                print("Gameover:", currentPlayer.status)
             isWhitesTurn = not isWhitesTurn
             currentPlayer = player1 if isWhitesTurn else player2
             need_to_draw = True
@@ -163,6 +160,8 @@ class Mouse:
 
 class Field:
     def __init__(self):
+        # The moves made so far in long algebraic notation, e.g. "e2e4"
+        self.history = []
         self.cells = []
         for x in range(8):
             self.cells.append([])
@@ -211,15 +210,29 @@ class Field:
 
     def move(self, move):
         # move = (startX, startY, endX, endY)
+        # A pawn moving diagonally onto an empty field hits en passant
+        if type(self.get(move[0], move[1])) is Pawn and move[0] != move[2]\
+            and self.get(move[2], move[3]) is None:
+            self.set(move[2], move[1], None)
         self.set(move[2], move[3], self.get(move[0], move[1]))
         self.set(move[0], move[1], None)
         # Make a Pawn only double jump once
         piece = self.get(move[2], move[3])
+        # A king moving two fields is castling, so move the rook as well
+        if type(piece) is King and abs(move[2] - move[0]) == 2:
+            rookX = 7 if move[2] > move[0] else 0
+            self.set((move[0] + move[2]) // 2, move[3], self.get(rookX, move[3]))
+            self.set(rookX, move[3], None)
         if type(piece) is Pawn:
             piece.has_first_move = False
             # If the pawn is at the end of the field, replace him with a queen
+            # or the figure chosen by the move
             if (move[3] == 0 and piece.color == 1) or (move[3] == 7 and piece.color ==-1):
-                self.set(move[2], move[3], Queen(piece.color))
+                promotion = move[4] if len(move) > 4 else "Queen"
+                figures = { "Queen": Queen, "Rook": Rook, "Bishop": Bishop, "Knight": Knight }
+                self.set(move[2], move[3], figures[promotion](piece.color))
+                move = move[:4] + (promotion,)
+        self.history.append(to_uci(move))
         return self
 
     def draw_all(self, screen):
@@ -252,28 +265,17 @@ class Field:
                     score += cell.color * color * cell.worth
         return score
 
-    def is_checkmate(self, color): # returns if the given color is in check
-        # Find the Rook
-        for x in range(8):
-            for y in range(8):
-                cell = self.get(x, y)
-                if cell:
-                    if type(cell) == King and cell.color == color:
-                        rook = cell
-                        rookX = x
-                        rookY = y
-        moves = self.get_possible_moves(color * -1)
-        for move in moves:
-            if move[2] == rookX and move[3] == rookY:
-                # The King is in Check
-                return True
-        return False
-
-    def toJson(self):
-        def figureToJson(fig):
-            color = "White" if fig.color == 1 else "Black"
-            return { "color": color, "figure": fig.name.title() }
-        return json.dumps(self.cells, default=figureToJson)
+    def game_request(self):
+        # The game for requests to the engine, which replays the moves itself
+        return { "fen": START_FEN, "moves": self.history }
+
+
+def to_uci(move):
+    # Like "e2e4", or "e7e8q" for a promotion. Row 0 is the 8th rank
+    text = "abcdefgh"[move[0]] + str(8 - move[1]) + "abcdefgh"[move[2]] + str(8 - move[3])
+    if len(move) > 4:
+        text += { "Queen": "q", "Rook": "r", "Bishop": "b", "Knight": "n" }[move[4]]
+    return text
 
 
 if __name__ == '__main__':
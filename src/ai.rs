@@ -1,96 +1,914 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
 use rand::prelude::*;
 use rayon::prelude::*;
 
-use crate::{board::*, figures::*};
+use crate::{
+    board::*,
+    eval::{self, PAWN_SCORE},
+    figures::*,
+    limits::{SearchLimits, TimeManager, MAX_DEPTH},
+    ordering::{is_tactical, Heuristics, MovePicker},
+    pawns::PawnTable,
+    position::Position,
+    score::{is_mate, mated_in, MATE_BOUND},
+    see::{cannot_lose, material_gain, see},
+    tt::{Bound, Entry, TranspositionTable, DEFAULT_SIZE_MB},
+};
 
-// Positive: Good for white, negative: Good for black
-pub fn total_score(board: &Board) -> Score {
-    board
-        .0
-        .iter()
-        .flatten()
-        .map(|cell| cell.map_or(0, Piece::score))
-        .sum()
+/// Which moves to generate, so that the search can try captures and
+/// promotions before even generating the quiet moves
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MoveKind {
+    All,
+    /// Captures and promotions, see `is_tactical`
+    Tactical,
+    Quiet,
+}
+
+impl MoveKind {
+    fn includes(self, board: &Board, mov: Move) -> bool {
+        match self {
+            MoveKind::All => true,
+            MoveKind::Tactical => is_tactical(board, mov),
+            MoveKind::Quiet => !is_tactical(board, mov),
+        }
+    }
 }
 
-pub fn get_all_possible_moves(board: &Board, whose_turn: Color) -> Vec<Move> {
+/// The moves of `kind` the pieces of `whose_turn` can make, ignoring whether
+/// they leave the own king in check
+pub fn pseudo_legal_moves(
+    board: &Board,
+    whose_turn: Color,
+    kind: MoveKind,
+) -> Vec<Move> {
     let mut result = Vec::new();
+    let mut push = |mov| {
+        if kind.includes(board, mov) {
+            result.push(mov);
+        }
+    };
     for y in 0..8 {
         for x in 0..8 {
             let from = (x, y);
             match board.get(from) {
                 Some(piece) if piece.color == whose_turn => {
+                    let promotes = promotes(board, from);
                     piece.reachables(from, board, |pos| {
-                        result.push(Move { from, to: pos })
+                        if promotes {
+                            for figure in PROMOTIONS {
+                                push(Move {
+                                    promotion: Some(figure),
+                                    ..Move::new(from, pos)
+                                });
+                            }
+                        } else {
+                            push(Move::new(from, pos))
+                        }
                     });
                 }
                 _ => {}
             }
         }
     }
+    if kind != MoveKind::Tactical {
+        board.castling_moves(whose_turn, push);
+    }
     result
 }
 
-pub fn calculate_best_move(
+/// Whether a move of the piece on `from` is a promotion
+fn promotes(board: &Board, from: Pos) -> bool {
+    board.get(from).is_some_and(|piece| {
+        piece.figure == Figure::Pawn
+            && from.1 + piece.color.forwards()
+                == piece.color.flipped().back_row()
+    })
+}
+
+/// All moves `whose_turn` can make without leaving the own king in check
+pub fn legal_moves(board: &Board, whose_turn: Color) -> Vec<Move> {
+    legal_moves_of_kind(board, whose_turn, MoveKind::All)
+}
+
+/// The legal moves of `whose_turn` of one kind
+pub fn legal_moves_of_kind(
     board: &Board,
     whose_turn: Color,
+    kind: MoveKind,
+) -> Vec<Move> {
+    let mut moves = pseudo_legal_moves(board, whose_turn, kind);
+    let king = match board.king_position(whose_turn) {
+        Some(king) => king,
+        None => return moves,
+    };
+    let (checkers, pinned) = board.checks_and_pins(whose_turn);
+    moves.retain(|&mov| {
+        // Moves of unpinned pieces other than the king can only expose the
+        // king if it already is in check. En passant removes a second piece
+        // from the line between king and attacker, so it is always checked
+        let may_expose_king = checkers > 0
+            || mov.from == king
+            || pinned.contains(&mov.from)
+            || board.is_en_passant(mov);
+        !may_expose_king || board.is_legal_after(mov)
+    });
+    moves
+}
+
+/// Whether `mov` is one of the legal moves of `whose_turn`, without
+/// generating all of them, e.g. for a move from the transposition table
+pub fn is_legal(board: &Board, whose_turn: Color, mov: Move) -> bool {
+    let piece = match board.get(mov.from) {
+        Some(piece) if piece.color == whose_turn => piece,
+        _ => return false,
+    };
+    let promotion_fits = match mov.promotion {
+        Some(figure) => {
+            promotes(board, mov.from) && PROMOTIONS.contains(&figure)
+        }
+        None => !promotes(board, mov.from),
+    };
+    let mut reachable = false;
+    piece.reachables(mov.from, board, |pos| reachable |= pos == mov.to);
+    if piece.figure == Figure::King {
+        board.castling_moves(whose_turn, |castling| {
+            reachable |= castling == mov
+        });
+    }
+    promotion_fits && reachable && board.is_legal_after(mov)
+}
+
+/// The outcome of a search
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Move,
+    /// The score of `best_move` in the view of the moving side
+    pub score: Score,
+    /// The moves both sides are expected to play, starting with `best_move`
+    pub pv: Vec<Move>,
+}
+
+#[allow(dead_code)]
+pub fn calculate_best_move(
+    position: &Position,
     max_look_ahead: usize,
 ) -> Option<Move> {
-    let moves = get_all_possible_moves(board, whose_turn);
-    let scores: Vec<Score> = moves
-        .par_iter()
-        .map(|&mov| {
-            let score =
-                calculate_future_score(board, mov, max_look_ahead, whose_turn);
-            score * whose_turn.as_number() as Score
-        })
-        .collect();
-    let max_score = scores.iter().max()?;
+    search(position, max_look_ahead).map(|result| result.best_move)
+}
+
+/// Bigger than any score a position can get
+const INFINITY: Score = Score::MAX;
+
+/// How much a capture may gain beyond the value of the captured piece, e.g.
+/// through a better position. Captures that can't reach alpha even then are
+/// skipped in quiescence search
+const DELTA_MARGIN: Score = 2 * PAWN_SCORE;
+
+/// How much better than alpha a quiet move one ply before the leaves has to
+/// be able to make the position to be searched (futility pruning)
+const FUTILITY_MARGIN: Score = 2 * PAWN_SCORE;
+
+/// How much the evaluation of a position may drop per ply left to search.
+/// Positions evaluated at least this much above beta aren't searched any
+/// further (reverse futility pruning)
+const REVERSE_FUTILITY_MARGIN: Score = 2 * PAWN_SCORE;
+
+/// How far from the score of the previous iteration the next one may be
+/// before it has to be searched again with a full window
+const ASPIRATION_WINDOW: Score = PAWN_SCORE / 2;
+
+/// Parts of the search that can be switched off, e.g. to measure how much
+/// they help
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchOptions {
+    /// Whether quiescence search tries all moves when in check instead of
+    /// only captures, so that it sees mates
+    pub check_evasions: bool,
+    /// Whether positions are cut off when even passing the turn to the
+    /// opponent keeps the score above beta
+    pub null_move: bool,
+    /// Whether quiet moves ordered late are searched less deep, unless they
+    /// turn out better than expected
+    pub late_move_reductions: bool,
+    /// Whether quiet moves right before the leaves are skipped when they
+    /// can't reach alpha
+    pub futility: bool,
+    /// Whether positions close to the leaves evaluated far above beta are cut
+    /// off without searching them
+    pub reverse_futility: bool,
+    /// Whether moves giving check are searched one ply deeper
+    pub check_extensions: bool,
+    /// Whether moves after the first are searched with a null window first,
+    /// which only tells whether they are better (principal variation search)
+    pub principal_variation_search: bool,
+    /// Whether each iteration starts with a window around the score of the
+    /// previous one
+    pub aspiration_windows: bool,
+}
+
+impl SearchOptions {
+    /// All parts switched on or all switched off
+    pub fn all(enabled: bool) -> Self {
+        Self {
+            check_evasions: enabled,
+            null_move: enabled,
+            late_move_reductions: enabled,
+            futility: enabled,
+            reverse_futility: enabled,
+            check_extensions: enabled,
+            principal_variation_search: enabled,
+            aspiration_windows: enabled,
+        }
+    }
+
+    /// The switches with the names chess GUIs show them with
+    pub fn switches(&mut self) -> [(&'static str, &mut bool); 8] {
+        [
+            ("Check Evasions", &mut self.check_evasions),
+            ("Null Move", &mut self.null_move),
+            ("Late Move Reductions", &mut self.late_move_reductions),
+            ("Futility", &mut self.futility),
+            ("Reverse Futility", &mut self.reverse_futility),
+            ("Check Extensions", &mut self.check_extensions),
+            ("PVS", &mut self.principal_variation_search),
+            ("Aspiration Windows", &mut self.aspiration_windows),
+        ]
+    }
+
+    /// The switch called `name`, ignoring case
+    pub fn switch(&mut self, name: &str) -> Option<&mut bool> {
+        IntoIterator::into_iter(self.switches())
+            .find(|(switch, _)| switch.eq_ignore_ascii_case(name))
+            .map(|(_, enabled)| enabled)
+    }
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self::all(true)
+    }
+}
+
+/// Like `calculate_best_move`, but also returns the score of the move and
+/// statistics about the search
+pub fn search(
+    position: &Position,
+    max_look_ahead: usize,
+) -> Option<SearchResult> {
+    let stop = AtomicBool::new(false);
+    let time_manager = TimeManager::new(&SearchLimits::default());
+    let tt = TranspositionTable::new(DEFAULT_SIZE_MB);
+    let options = SearchOptions::default();
+    search_root(
+        position,
+        max_look_ahead,
+        (-INFINITY, INFINITY),
+        &Context::new(&stop, &time_manager, &tt, &options),
+    )
+}
+
+/// The score of `position` without searching, in the view of the side to
+/// move
+fn evaluate(position: &Position, pawns: &mut PawnTable) -> Score {
+    eval::evaluate(&position.board, pawns)
+        * position.side_to_move.as_number() as Score
+}
+
+/// Shared by all threads working on the same search
+struct Context<'a> {
+    /// Positions visited in all iterations so far
+    nodes: AtomicU64,
+    /// Set from outside to stop the search
+    stop: &'a AtomicBool,
+    time_manager: &'a TimeManager,
+    tt: &'a TranspositionTable,
+    options: &'a SearchOptions,
+    /// Set once the search ran out of time or nodes or was stopped, after
+    /// which all scores are meaningless
+    aborted: AtomicBool,
+    /// Unset during the first iteration, which is always completed so that
+    /// there is a move to play
+    abortable: AtomicBool,
+}
+
+impl<'a> Context<'a> {
+    /// Looking at the clock is too expensive to do for every node. A power of
+    /// two, so that it's checked by masking the node count
+    const CHECK_INTERVAL: u64 = 1024;
+
+    fn new(
+        stop: &'a AtomicBool,
+        time_manager: &'a TimeManager,
+        tt: &'a TranspositionTable,
+        options: &'a SearchOptions,
+    ) -> Self {
+        Self {
+            nodes: AtomicU64::new(0),
+            stop,
+            time_manager,
+            tt,
+            options,
+            aborted: AtomicBool::new(false),
+            abortable: AtomicBool::new(false),
+        }
+    }
+
+    /// Counts a node and returns whether the search has to be aborted
+    fn visit(&self) -> bool {
+        let nodes = self.nodes.fetch_add(1, Ordering::Relaxed) + 1;
+        if !self.abortable.load(Ordering::Relaxed) {
+            return false;
+        }
+        if self.aborted.load(Ordering::Relaxed) {
+            return true;
+        }
+        if self.stop.load(Ordering::Relaxed)
+            || self.time_manager.out_of_nodes(nodes)
+            || (nodes & (Self::CHECK_INTERVAL - 1) == 0
+                && self.time_manager.out_of_time())
+        {
+            self.aborted.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Relaxed)
+    }
+}
+
+/// The state of a single search thread
+struct Worker<'a> {
+    context: &'a Context<'a>,
+    heuristics: Heuristics,
+    pawns: PawnTable,
+    /// Set right before searching the position after a null move, so that
+    /// two null moves aren't made in a row
+    after_null_move: bool,
+}
+
+impl<'a> Worker<'a> {
+    fn new(context: &'a Context<'a>) -> Self {
+        Self {
+            context,
+            heuristics: Heuristics::new(),
+            pawns: PawnTable::new(),
+            after_null_move: false,
+        }
+    }
+}
+
+/// Searches all moves, `None` if there are none or the search was aborted.
+/// Scores outside of `window` are only bounds, like for `alpha_beta`
+fn search_root(
+    position: &Position,
+    max_look_ahead: usize,
+    (alpha, beta): (Score, Score),
+    context: &Context,
+) -> Option<SearchResult> {
+    let mut moves = position.legal_moves();
+    let search_move =
+        |worker: &mut Worker, mov: Move, (alpha, beta): (Score, Score)| {
+            let mut position = position.clone();
+            let mut line = Vec::new();
+            context.visit();
+            position.make_move(mov);
+            let score = -alpha_beta(
+                &mut position,
+                max_look_ahead,
+                1,
+                -beta,
+                -alpha,
+                worker,
+                &mut line,
+            );
+            line.insert(0, mov);
+            (score, line)
+        };
+    let mut results: Vec<(Score, Vec<Move>)> = Vec::new();
+    if context.options.principal_variation_search {
+        // The best move of the last iteration most likely is the best again
+        let best_known = context
+            .tt
+            .probe(position.hash(), 0)
+            .and_then(|entry| entry.best_move);
+        if let Some(index) =
+            moves.iter().position(|&mov| Some(mov) == best_known)
+        {
+            moves[..=index].rotate_right(1);
+        }
+        let first = *moves.first()?;
+        let first =
+            search_move(&mut Worker::new(context), first, (alpha, beta));
+        let bar = alpha.max(first.0);
+        results.push(first);
+        // The other moves only have to be shown worse than the first, with a
+        // null window just below its score. Those that aren't might be equally
+        // good and get their exact score, so that a random one of all equally
+        // good moves can be chosen
+        if bar < beta {
+            results.par_extend(moves[1..].par_iter().map_init(
+                || Worker::new(context),
+                |worker, &mov| {
+                    let result = search_move(worker, mov, (bar - 1, bar));
+                    if result.0 >= bar && result.0 < beta {
+                        search_move(worker, mov, (bar - 1, beta))
+                    } else {
+                        result
+                    }
+                },
+            ));
+        }
+    } else {
+        // Every move gets its exact score inside the window
+        results.par_extend(moves.par_iter().map_init(
+            || Worker::new(context),
+            |worker, &mov| search_move(worker, mov, (alpha, beta)),
+        ));
+    }
+    if context.is_aborted() {
+        return None;
+    }
+    let max_score = results.iter().map(|&(score, _)| score).max()?;
     // From the indices of values wich are all equal to the max, choose a random one
-    let index = scores
+    let index = results
         .iter()
         .enumerate()
-        .filter(|&(_, score)| score == max_score)
+        .filter(|&(_, &(score, _))| score == max_score)
         .choose(&mut rand::thread_rng())
         .unwrap()
         .0;
-    Some(moves[index])
+    let bound = if max_score >= beta {
+        Bound::Lower
+    } else if max_score > alpha {
+        Bound::Exact
+    } else {
+        Bound::Upper
+    };
+    context.tt.store(
+        position.hash(),
+        0,
+        Entry {
+            best_move: (bound != Bound::Upper).then_some(moves[index]),
+            score: max_score,
+            depth: max_look_ahead + 1,
+            bound,
+        },
+    );
+    Some(SearchResult {
+        best_move: moves[index],
+        score: max_score,
+        pv: results[index].1.clone(),
+    })
     //.expect("Can't find move to make (AI was checkmated)")
 }
 
-fn calculate_future_score(
-    board: &Board,
-    mov: Move,
+/// Negamax search with alpha-beta pruning. Returns the score of `position`
+/// in the view of the side to move, looking `look_ahead` more moves ahead.
+/// `ply` is the number of moves made since the root of the search. Scores
+/// outside of `alpha..beta` are only bounds of the real score. If the score
+/// is inside, `pv` is set to the best line of moves
+fn alpha_beta(
+    position: &mut Position,
     look_ahead: usize,
-    whose_turn: Color,
+    ply: usize,
+    mut alpha: Score,
+    beta: Score,
+    worker: &mut Worker,
+    pv: &mut Vec<Move>,
 ) -> Score {
-    let mut copy = board.clone();
-    debug_assert_eq!(
-        copy.get(mov.from).map(|piece| piece.color),
-        Some(whose_turn)
-    );
-    debug_assert_ne!(
-        copy.get(mov.to).map(|piece| piece.color),
-        Some(whose_turn)
-    );
-    copy.do_move(mov);
+    let after_null_move = std::mem::take(&mut worker.after_null_move);
     if look_ahead == 0 {
-        return total_score(&copy);
-    }
-    // Now calculate the scores for all the moves the opponent can do
-    let moves = get_all_possible_moves(&copy, whose_turn.flipped());
-    let scores = moves.iter().map(|&enemy_mov| {
-        calculate_future_score(
-            &copy,
-            enemy_mov,
-            look_ahead - 1,
-            whose_turn.flipped(),
-        )
-    });
-    match whose_turn.flipped() {
-        // The enemy chooses the the best score for itself
-        Color::White => scores.max().unwrap_or(-10_000),
-        Color::Black => scores.min().unwrap_or(10_000),
+        return quiescence(position, ply, alpha, beta, worker);
+    }
+    let context = worker.context;
+    let options = context.options;
+    if position.draw_status().is_some() {
+        return 0;
+    }
+    let whose_turn = position.side_to_move;
+    let hash = position.hash();
+    let entry = context.tt.probe(hash, ply);
+    if let Some(entry) = entry.filter(|entry| entry.depth >= look_ahead) {
+        let usable = match entry.bound {
+            Bound::Exact => true,
+            Bound::Lower => entry.score >= beta,
+            Bound::Upper => entry.score <= alpha,
+        };
+        if usable {
+            if entry.score > alpha && entry.score < beta {
+                *pv = line_from_table(position, ply, look_ahead, context.tt);
+            }
+            return entry.score;
+        }
+    }
+    let in_check = position.board.is_in_check(whose_turn);
+    let static_score = evaluate(position, &mut worker.pawns);
+    // Far above beta, the opponent won't allow this position even if it gets
+    // a bit worse in the remaining plies
+    if options.reverse_futility
+        && look_ahead <= 2
+        && !in_check
+        && !is_mate(beta)
+        && static_score - REVERSE_FUTILITY_MARGIN * look_ahead as Score >= beta
+    {
+        return static_score - REVERSE_FUTILITY_MARGIN * look_ahead as Score;
+    }
+    // If passing still keeps the score above beta, a real move would as well,
+    // unless the side to move is in zugzwang. That happens mostly in pawn
+    // endgames, which is why they are left out
+    if options.null_move
+        && !after_null_move
+        && !in_check
+        && look_ahead >= 3
+        && beta < MATE_BOUND
+        && static_score >= beta
+        && has_pieces(&position.board, whose_turn)
+    {
+        let reduction = if look_ahead > 6 { 3 } else { 2 };
+        let null_move = position.make_null_move();
+        worker.after_null_move = true;
+        let score = -alpha_beta(
+            position,
+            look_ahead - 1 - reduction,
+            ply + 1,
+            -beta,
+            -beta + 1,
+            worker,
+            &mut Vec::new(),
+        );
+        position.unmake_null_move(null_move);
+        if score >= beta {
+            return beta;
+        }
+    }
+    // The best move of an earlier search is likely still good
+    let mut picker = MovePicker::new(
+        entry.and_then(|entry| entry.best_move),
+        true,
+        whose_turn,
+        ply,
+        &worker.heuristics,
+    );
+    let original_alpha = alpha;
+    let mut best = -INFINITY;
+    let mut best_move = None;
+    let mut searched = 0;
+    let mut line = Vec::new();
+    while let Some(mov) = picker.next(&position.board, &worker.heuristics) {
+        let quiet = !is_tactical(&position.board, mov);
+        position.make_move(mov);
+        let gives_check = position.board.is_in_check(whose_turn.flipped());
+        // Right before the leaves, a quiet move can't win much
+        if options.futility
+            && look_ahead == 1
+            && searched > 0
+            && quiet
+            && !in_check
+            && !gives_check
+            && static_score + FUTILITY_MARGIN <= alpha
+        {
+            position.unmake_move();
+            best = best.max(static_score + FUTILITY_MARGIN);
+            continue;
+        }
+        if context.visit() {
+            position.unmake_move();
+            return 0;
+        }
+        // Checks may lead to mates and are searched deeper, but long series
+        // of checks mustn't make the search go on forever
+        let extension = usize::from(
+            options.check_extensions
+                && gives_check
+                && ply + look_ahead < MAX_DEPTH,
+        );
+        let depth = look_ahead - 1 + extension;
+        // Moves ordered late rarely are the best, so they are searched less
+        // deep first
+        let reduction = if options.late_move_reductions
+            && searched >= 3
+            && look_ahead >= 3
+            && quiet
+            && !in_check
+            && !gives_check
+        {
+            if searched >= 6 && look_ahead >= 6 {
+                2
+            } else {
+                1
+            }
+        } else {
+            0
+        };
+        // After the first move, the others only have to be shown worse
+        let null_window = options.principal_variation_search && searched > 0;
+        let first_beta = if null_window { alpha + 1 } else { beta };
+        line.clear();
+        let mut score = -alpha_beta(
+            position,
+            depth - reduction,
+            ply + 1,
+            -first_beta,
+            -alpha,
+            worker,
+            &mut line,
+        );
+        if reduction > 0 && score > alpha {
+            line.clear();
+            score = -alpha_beta(
+                position,
+                depth,
+                ply + 1,
+                -first_beta,
+                -alpha,
+                worker,
+                &mut line,
+            );
+        }
+        if null_window && score > alpha && score < beta {
+            line.clear();
+            score = -alpha_beta(
+                position,
+                depth,
+                ply + 1,
+                -beta,
+                -alpha,
+                worker,
+                &mut line,
+            );
+        }
+        position.unmake_move();
+        searched += 1;
+        if score > best {
+            best = score;
+            best_move = Some(mov);
+        }
+        if score > alpha {
+            alpha = score;
+            pv.clear();
+            pv.push(mov);
+            pv.extend_from_slice(&line);
+        }
+        if alpha >= beta {
+            // The opponent won't allow this position
+            worker.heuristics.record_cutoff(
+                &position.board,
+                whose_turn,
+                mov,
+                ply,
+                look_ahead,
+            );
+            break;
+        }
+    }
+    // The first move is never pruned, so there are none
+    if searched == 0 {
+        return if in_check {
+            // Checkmate
+            mated_in(ply)
+        } else {
+            // Stalemate
+            0
+        };
+    }
+    if !context.is_aborted() {
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        context.tt.store(
+            hash,
+            ply,
+            Entry {
+                // If no move reached alpha, none of them is known to be best
+                best_move: best_move.filter(|_| bound != Bound::Upper),
+                score: best,
+                depth: look_ahead,
+                bound,
+            },
+        );
+    }
+    best
+}
+
+/// Whether `color` has pieces other than pawns and the king
+fn has_pieces(board: &Board, color: Color) -> bool {
+    board.cells.iter().flatten().flatten().any(|piece| {
+        piece.color == color
+            && !matches!(piece.figure, Figure::Pawn | Figure::King)
+    })
+}
+
+/// Searches captures and promotions until the position is quiet, so that it
+/// isn't evaluated in the middle of an exchange. Not capturing is allowed as
+/// well, which is why the evaluation is a lower bound of the score ("stand
+/// pat"). Arguments and result are like for `alpha_beta`
+fn quiescence(
+    position: &mut Position,
+    ply: usize,
+    mut alpha: Score,
+    beta: Score,
+    worker: &mut Worker,
+) -> Score {
+    let context = worker.context;
+    if position.draw_status().is_some() {
+        return 0;
+    }
+    let whose_turn = position.side_to_move;
+    // When in check, not moving isn't an option and all moves are tried
+    let evading = context.options.check_evasions
+        && position.board.is_in_check(whose_turn);
+    let stand_pat = evaluate(position, &mut worker.pawns);
+    let mut best = -INFINITY;
+    if !evading {
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+        best = stand_pat;
+    }
+    let mut picker =
+        MovePicker::new(None, evading, whose_turn, ply, &worker.heuristics);
+    while let Some(mov) = picker.next(&position.board, &worker.heuristics) {
+        if !evading {
+            // Delta pruning
+            if stand_pat + material_gain(&position.board, mov) + DELTA_MARGIN
+                <= alpha
+            {
+                continue;
+            }
+            // Captures losing material won't raise the score either
+            if !cannot_lose(&position.board, mov)
+                && see(&position.board, mov) < 0
+            {
+                continue;
+            }
+        }
+        if context.visit() {
+            return 0;
+        }
+        position.make_move(mov);
+        let score = -quiescence(position, ply + 1, -beta, -alpha, worker);
+        position.unmake_move();
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+    if best == -INFINITY {
+        // Evading, but there is no move: Checkmate
+        return mated_in(ply);
+    }
+    best
+}
+
+/// The line of best moves stored in the transposition table, starting at
+/// `position`, which is `ply` moves after the root
+fn line_from_table(
+    position: &mut Position,
+    ply: usize,
+    max_length: usize,
+    tt: &TranspositionTable,
+) -> Vec<Move> {
+    let mut line = Vec::new();
+    while line.len() < max_length {
+        let mov = match tt
+            .probe(position.hash(), ply + line.len())
+            .and_then(|entry| entry.best_move)
+        {
+            Some(mov) => mov,
+            None => break,
+        };
+        // Another position might have overwritten the entry, or have the
+        // same hash
+        if !position.legal_moves().contains(&mov) {
+            break;
+        }
+        position.make_move(mov);
+        line.push(mov);
+    }
+    for _ in &line {
+        position.unmake_move();
+    }
+    line
+}
+
+/// What is known after completing the search to one more depth
+pub struct Iteration<'a> {
+    /// Plies searched
+    pub depth: usize,
+    pub result: &'a SearchResult,
+    /// Nodes searched in all iterations so far
+    pub nodes: u64,
+    pub elapsed: Duration,
+}
+
+/// Searches one ply deeper after each other until `limits` are reached or
+/// `stop` is set, reporting each completed depth. An unfinished depth is
+/// thrown away, except for the first one which is always completed. Returns
+/// the result of the deepest completed search, `None` if there are no legal
+/// moves. What was learned is kept in `tt` for later searches
+pub fn iterative_deepening(
+    position: &Position,
+    limits: &SearchLimits,
+    options: &SearchOptions,
+    tt: &TranspositionTable,
+    stop: &AtomicBool,
+    mut report: impl FnMut(&Iteration),
+) -> Option<SearchResult> {
+    let time_manager = TimeManager::new(limits);
+    tt.new_search();
+    let context = Context::new(stop, &time_manager, tt, options);
+    let mut best = None;
+    for depth in 1..=limits.max_depth() {
+        let iteration_start = time_manager.elapsed();
+        // The score usually changes little from one depth to the next, and a
+        // narrow window cuts off more. If the score falls outside, it is
+        // searched again with that side of the window opened
+        let mut window = match &best {
+            Some(SearchResult { score, .. })
+                if options.aspiration_windows && !is_mate(*score) =>
+            {
+                (score - ASPIRATION_WINDOW, score + ASPIRATION_WINDOW)
+            }
+            _ => (-INFINITY, INFINITY),
+        };
+        let result = loop {
+            match search_root(position, depth - 1, window, &context) {
+                Some(result) if result.score <= window.0 => {
+                    window.0 = -INFINITY
+                }
+                Some(result) if result.score >= window.1 => window.1 = INFINITY,
+                result => break result,
+            }
+        };
+        let result = match result {
+            Some(result) => result,
+            None => break,
+        };
+        let nodes = context.nodes.load(Ordering::Relaxed);
+        let elapsed = time_manager.elapsed();
+        report(&Iteration {
+            depth,
+            result: &result,
+            nodes,
+            elapsed,
+        });
+        best = Some(result);
+        context.abortable.store(true, Ordering::Relaxed);
+        if stop.load(Ordering::Relaxed)
+            || !time_manager.should_continue(elapsed - iteration_start, nodes)
+        {
+            break;
+        }
+    }
+    best
+}
+
+/// A search running on a thread of its own, so that it can be stopped while
+/// the caller goes on with other things
+pub struct SearchHandle<T> {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<T>,
+}
+
+impl<T: Send + 'static> SearchHandle<T> {
+    /// Runs `search` on a new thread. It gets the flag set by `stop`, meant to
+    /// be passed on to `iterative_deepening`
+    pub fn spawn(
+        search: impl FnOnce(&AtomicBool) -> T + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        Self {
+            stop,
+            thread: std::thread::spawn(move || search(&thread_stop)),
+        }
+    }
+
+    /// Tells the search to finish as soon as possible
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Waits for the search to finish and returns its result
+    pub fn join(self) -> T {
+        self.thread.join().expect("Search thread panicked")
     }
 }
 
@@ -122,16 +940,588 @@ fn random_max_by_key<'a, T: Copy + 'a, U: PartialEq + Ord + std::fmt::Debug>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{position::GameStatus, score::mate_in};
+    fn perft(board: &Board, whose_turn: Color, depth: usize) -> usize {
+        if depth == 0 {
+            return 1;
+        }
+        legal_moves(board, whose_turn)
+            .into_iter()
+            .map(|mov| {
+                let mut copy = board.clone();
+                copy.do_move(mov);
+                perft(&copy, whose_turn.flipped(), depth - 1)
+            })
+            .sum()
+    }
+
+    #[test]
+    fn perft_new_field() {
+        let board = Board::new();
+        assert_eq!(perft(&board, Color::White, 1), 20);
+        assert_eq!(perft(&board, Color::White, 2), 400);
+        assert_eq!(perft(&board, Color::White, 3), 8902);
+    }
+
+    #[test]
+    fn perft_fen_positions() {
+        for (fen, counts) in [
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                [48, 2039, 97862],
+            ),
+            (
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                [6, 264, 9467],
+            ),
+            (
+                "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                [44, 1486, 62379],
+            ),
+        ] {
+            let position = Position::from_fen(fen).unwrap();
+            for (depth, &count) in counts.iter().enumerate() {
+                assert_eq!(
+                    perft(&position.board, position.side_to_move, depth + 1),
+                    count,
+                    "{} at depth {}",
+                    fen,
+                    depth + 1
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn king_does_not_walk_into_check() {
+        let mut board = Board::empty();
+        board.cells[4][7] = Some(Piece {
+            color: Color::White,
+            figure: Figure::King,
+        });
+        board.cells[3][0] = Some(Piece {
+            color: Color::Black,
+            figure: Figure::Rook,
+        });
+        let mut targets: Vec<Pos> = legal_moves(&board, Color::White)
+            .into_iter()
+            .map(|mov| mov.to)
+            .collect();
+        targets.sort();
+        assert_eq!(targets, vec![(4, 6), (5, 6), (5, 7)]);
+    }
+
+    #[test]
+    fn pinned_piece_stays_on_line() {
+        let mut board = Board::empty();
+        board.cells[4][7] = Some(Piece {
+            color: Color::White,
+            figure: Figure::King,
+        });
+        board.cells[4][5] = Some(Piece {
+            color: Color::White,
+            figure: Figure::Rook,
+        });
+        board.cells[4][0] = Some(Piece {
+            color: Color::Black,
+            figure: Figure::Queen,
+        });
+        let (checkers, pinned) = board.checks_and_pins(Color::White);
+        assert_eq!((checkers, pinned), (0, vec![(4, 5)]));
+        assert!(legal_moves(&board, Color::White)
+            .into_iter()
+            .filter(|mov| mov.from == (4, 5))
+            .all(|mov| mov.to.0 == 4));
+    }
+
+    fn castling_board() -> Board {
+        let mut board = Board::empty();
+        board.set((4, 7), Some(Piece::new(Color::White, Figure::King)));
+        board.set((0, 7), Some(Piece::new(Color::White, Figure::Rook)));
+        board.set((7, 7), Some(Piece::new(Color::White, Figure::Rook)));
+        board.set((4, 0), Some(Piece::new(Color::Black, Figure::King)));
+        board.castling.white_king_side = true;
+        board.castling.white_queen_side = true;
+        board
+    }
+
+    fn castles(board: &Board, whose_turn: Color) -> Vec<Pos> {
+        let mut targets: Vec<Pos> = legal_moves(board, whose_turn)
+            .into_iter()
+            .filter(|mov| {
+                board.get(mov.from).unwrap().figure == Figure::King
+                    && (mov.to.0 - mov.from.0).abs() == 2
+            })
+            .map(|mov| mov.to)
+            .collect();
+        targets.sort();
+        targets
+    }
+
     #[test]
-    fn default_score() {
-        assert_eq!(total_score(&Board::new()), 0);
+    fn castling_to_both_sides() {
+        let board = castling_board();
+        assert_eq!(castles(&board, Color::White), vec![(2, 7), (6, 7)]);
+        let mut no_rights = board.clone();
+        no_rights.castling = CastlingRights::NONE;
+        assert!(castles(&no_rights, Color::White).is_empty());
+    }
+
+    #[test]
+    fn no_castling_out_of_through_or_into_check() {
+        let mut board = castling_board();
+        // Attacks the field the king passes when castling king side
+        board.set((5, 0), Some(Piece::new(Color::Black, Figure::Rook)));
+        assert_eq!(castles(&board, Color::White), vec![(2, 7)]);
+        // Attacks the field the king lands on when castling queen side
+        board.set((2, 0), Some(Piece::new(Color::Black, Figure::Rook)));
+        assert!(castles(&board, Color::White).is_empty());
+        let mut board = castling_board();
+        board.set((4, 3), Some(Piece::new(Color::Black, Figure::Rook)));
+        assert!(castles(&board, Color::White).is_empty());
+    }
+
+    #[test]
+    fn castling_moves_rook_and_ends_rights() {
+        let mut board = castling_board();
+        board.do_move(Move::new((4, 7), (6, 7)));
+        assert_eq!(
+            board.get((5, 7)),
+            Some(Piece::new(Color::White, Figure::Rook))
+        );
+        assert_eq!(board.get((7, 7)), None);
+        assert_eq!(board.castling, CastlingRights::NONE);
+
+        let mut board = castling_board();
+        board.do_move(Move::new((0, 7), (0, 6)));
+        assert!(board.castling.white_king_side);
+        assert!(!board.castling.white_queen_side);
+    }
+
+    #[test]
+    fn castling_rights_from_cells() {
+        assert_eq!(
+            Board::from(Board::new().cells).castling,
+            CastlingRights::ALL
+        );
+        assert_eq!(
+            Board::from(castling_board().cells).castling,
+            castling_board().castling
+        );
+    }
+
+    #[test]
+    fn en_passant() {
+        let mut board = Board::empty();
+        board.set((4, 4), Some(Piece::new(Color::Black, Figure::Pawn)));
+        board.set((3, 6), Some(Piece::new(Color::White, Figure::Pawn)));
+        board.do_move(Move::new((3, 6), (3, 4)));
+        assert_eq!(board.en_passant, Some((3, 5)));
+        let hit = Move::new((4, 4), (3, 5));
+        assert!(legal_moves(&board, Color::Black)
+            .iter()
+            .any(|mov| mov.from == hit.from && mov.to == hit.to));
+        board.do_move(hit);
+        assert_eq!(board.get((3, 4)), None);
+        assert_eq!(
+            board.get((3, 5)),
+            Some(Piece::new(Color::Black, Figure::Pawn))
+        );
+        assert_eq!(board.en_passant, None);
+    }
+
+    #[test]
+    fn perft_en_passant_and_discovered_checks() {
+        // 8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - -
+        let mut board = Board::empty();
+        for (pos, color, figure) in [
+            ((2, 1), Color::Black, Figure::Pawn),
+            ((3, 2), Color::Black, Figure::Pawn),
+            ((0, 3), Color::White, Figure::King),
+            ((1, 3), Color::White, Figure::Pawn),
+            ((7, 3), Color::Black, Figure::Rook),
+            ((1, 4), Color::White, Figure::Rook),
+            ((5, 4), Color::Black, Figure::Pawn),
+            ((7, 4), Color::Black, Figure::King),
+            ((4, 6), Color::White, Figure::Pawn),
+            ((6, 6), Color::White, Figure::Pawn),
+        ] {
+            board.set(pos, Some(Piece::new(color, figure)));
+        }
+        assert_eq!(perft(&board, Color::White, 1), 14);
+        assert_eq!(perft(&board, Color::White, 2), 191);
+        assert_eq!(perft(&board, Color::White, 3), 2812);
+        assert_eq!(perft(&board, Color::White, 4), 43238);
+    }
+
+    #[test]
+    fn all_promotions() {
+        let mut board = Board::empty();
+        board.set((1, 1), Some(Piece::new(Color::White, Figure::Pawn)));
+        board.set((0, 0), Some(Piece::new(Color::Black, Figure::Rook)));
+        let moves = legal_moves(&board, Color::White);
+        assert_eq!(moves.len(), 8);
+        for to in [(0, 0), (1, 0)] {
+            let mut promotions: Vec<_> = moves
+                .iter()
+                .filter(|mov| mov.to == to)
+                .map(|mov| mov.promotion.unwrap())
+                .collect();
+            promotions.sort_by_key(|&figure| figure as u8);
+            assert_eq!(
+                promotions,
+                vec![
+                    Figure::Bishop,
+                    Figure::Knight,
+                    Figure::Rook,
+                    Figure::Queen
+                ]
+            );
+        }
+        board.do_move(Move {
+            promotion: Some(Figure::Knight),
+            ..Move::new((1, 1), (0, 0))
+        });
+        assert_eq!(
+            board.get((0, 0)),
+            Some(Piece::new(Color::White, Figure::Knight))
+        );
+    }
+
+    #[test]
+    fn move_serialization() {
+        let mov = Move::new((1, 6), (1, 4));
+        let json = serde_json::to_string(&mov).unwrap();
+        assert_eq!(json, r#"{"from":[1,6],"to":[1,4]}"#);
+        assert_eq!(serde_json::from_str::<Move>(&json).unwrap(), mov);
+        let mov = Move {
+            promotion: Some(Figure::Knight),
+            ..Move::new((1, 1), (1, 0))
+        };
+        let json = serde_json::to_string(&mov).unwrap();
+        assert_eq!(json, r#"{"from":[1,1],"to":[1,0],"promotion":"Knight"}"#);
+        assert_eq!(serde_json::from_str::<Move>(&json).unwrap(), mov);
+    }
+
+    #[test]
+    fn no_moves_when_checkmated() {
+        let mut board = Board::empty();
+        board.cells[7][7] = Some(Piece {
+            color: Color::White,
+            figure: Figure::King,
+        });
+        board.cells[0][7] = Some(Piece {
+            color: Color::Black,
+            figure: Figure::Rook,
+        });
+        board.cells[1][6] = Some(Piece {
+            color: Color::Black,
+            figure: Figure::Rook,
+        });
+        assert!(board.is_in_check(Color::White));
+        assert!(legal_moves(&board, Color::White).is_empty());
+        let position = Position::from_board(board, Color::White);
+        assert!(calculate_best_move(&position, 2).is_none());
+    }
+
+    #[test]
+    fn mates_instead_of_stalemating() {
+        let mut board = Board::empty();
+        board.set((0, 0), Some(Piece::new(Color::Black, Figure::King)));
+        board.set((2, 2), Some(Piece::new(Color::White, Figure::King)));
+        board.set((1, 4), Some(Piece::new(Color::White, Figure::Queen)));
+        let mut position = Position::from_board(board, Color::White);
+        let mov = calculate_best_move(&position, 1).unwrap();
+        position.make_move(mov);
+        assert_eq!(
+            position.status(),
+            GameStatus::Checkmate {
+                winner: Color::White
+            }
+        );
+    }
+
+    /// The plain minimax search alpha-beta replaced, in the view of the side
+    /// to move. The leaves get the exact score of quiescence search
+    fn minimax(
+        position: &mut Position,
+        look_ahead: usize,
+        ply: usize,
+        worker: &mut Worker,
+    ) -> Score {
+        if look_ahead == 0 {
+            return quiescence(position, ply, -INFINITY, INFINITY, worker);
+        }
+        if position.draw_status().is_some() {
+            return 0;
+        }
+        let whose_turn = position.side_to_move;
+        let moves = position.legal_moves();
+        if moves.is_empty() {
+            return if position.board.is_in_check(whose_turn) {
+                mated_in(ply)
+            } else {
+                0
+            };
+        }
+        moves
+            .into_iter()
+            .map(|mov| {
+                position.make_move(mov);
+                let score = -minimax(position, look_ahead - 1, ply + 1, worker);
+                position.unmake_move();
+                score
+            })
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn alpha_beta_scores_like_minimax() {
+        let stop = AtomicBool::new(false);
+        let time_manager = TimeManager::new(&SearchLimits::default());
+        // Selective search may find other scores than minimax, though not in
+        // these positions at this depth
+        for options in [SearchOptions::all(false), SearchOptions::default()] {
+            let tt = TranspositionTable::new(1);
+            let context = Context::new(&stop, &time_manager, &tt, &options);
+            for fen in [
+                "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+                "4k3/8/2p5/3p4/8/8/3R4/3QK3 b - - 0 1",
+                "6k1/5ppp/8/8/8/8/5PPP/R5K1 b - - 0 1",
+            ] {
+                let mut position = Position::from_fen(fen).unwrap();
+                let result =
+                    search_root(&position, 2, (-INFINITY, INFINITY), &context)
+                        .unwrap();
+                let score =
+                    minimax(&mut position, 3, 0, &mut Worker::new(&context));
+                assert_eq!(result.score, score, "{} {:?}", fen, options);
+                // The principal variation is a line of legal moves
+                assert_eq!(result.pv[0], result.best_move);
+                assert!(!result.pv.is_empty() && result.pv.len() <= 3);
+                for &mov in &result.pv {
+                    assert!(position.legal_moves().contains(&mov), "{}", fen);
+                    position.make_move(mov);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ties_are_broken_randomly() {
+        // Every king move leads to a draw
+        let position =
+            Position::from_fen("8/8/8/3k4/8/8/8/4K3 w - - 0 1").unwrap();
+        let mut chosen: Vec<Move> = (0..20)
+            .map(|_| search(&position, 1).unwrap().best_move)
+            .collect();
+        chosen.sort_by_key(|mov| mov.to);
+        chosen.dedup();
+        assert!(chosen.len() > 1);
+    }
+
+    #[test]
+    fn sees_recaptures_at_the_horizon() {
+        // Taking the pawn loses the queen
+        let position =
+            Position::from_fen("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1").unwrap();
+        let result = search(&position, 0).unwrap();
+        assert_ne!(result.best_move.to_uci(), "d1d5");
+        // A queen against two pawns
+        assert!((6 * PAWN_SCORE..8 * PAWN_SCORE).contains(&result.score));
+    }
+
+    #[test]
+    fn principal_variation_ends_in_mate() {
+        // Nothing follows the mate, even though two more plies are searched
+        let position =
+            Position::from_fen("6k1/5ppp/8/8/8/8/r4PPP/1R4K1 w - - 0 1")
+                .unwrap();
+        let result = search(&position, 2).unwrap();
+        assert_eq!(result.score, mate_in(1));
+        let pv: Vec<String> =
+            result.pv.iter().map(|mov| mov.to_uci()).collect();
+        assert_eq!(pv, ["b1b8"]);
+    }
+
+    #[test]
+    fn every_option_alone_finds_mates_and_tactics() {
+        let mut configurations = vec![SearchOptions::all(true)];
+        for index in 0..SearchOptions::all(false).switches().len() {
+            let mut options = SearchOptions::all(false);
+            *options.switches()[index].1 = true;
+            configurations.push(options);
+        }
+        let stop = AtomicBool::new(false);
+        let tt = TranspositionTable::new(1);
+        for options in &configurations {
+            let search = |fen| {
+                tt.clear();
+                let position = Position::from_fen(fen).unwrap();
+                let limits = SearchLimits::depth(4);
+                iterative_deepening(
+                    &position,
+                    &limits,
+                    options,
+                    &tt,
+                    &stop,
+                    |_| {},
+                )
+                .unwrap()
+            };
+            // Mate in two: Kc7 Ka7 Ra1
+            let result = search("k7/8/2K5/8/8/8/8/7R w - - 0 1");
+            assert_eq!(result.score, mate_in(3), "{:?}", options);
+            // The check skewers the queen behind the king
+            let result = search("3q4/8/8/3k4/8/8/8/R3K3 w - - 0 1");
+            assert_eq!(result.best_move.to_uci(), "a1d1", "{:?}", options);
+        }
+    }
+
+    #[test]
+    fn stops_at_the_node_limit() {
+        let limits = SearchLimits {
+            nodes: Some(500),
+            ..SearchLimits::default()
+        };
+        let stop = AtomicBool::new(false);
+        let time_manager = TimeManager::new(&limits);
+        let tt = TranspositionTable::new(1);
+        let options = SearchOptions::default();
+        let context = Context::new(&stop, &time_manager, &tt, &options);
+        context.abortable.store(true, Ordering::Relaxed);
+        let visits = (0..1000).take_while(|_| !context.visit()).count();
+        assert_eq!(visits, 499);
+    }
+
+    #[test]
+    fn deepens_until_limits() {
+        let position = Position::new();
+        let stop = AtomicBool::new(false);
+        let tt = TranspositionTable::new(1);
+        let mut depths = Vec::new();
+        let result = iterative_deepening(
+            &position,
+            &SearchLimits::depth(3),
+            &SearchOptions::default(),
+            &tt,
+            &stop,
+            |iteration| depths.push(iteration.depth),
+        );
+        assert!(result.is_some());
+        assert_eq!(depths, [1, 2, 3]);
+
+        let limits = SearchLimits {
+            nodes: Some(500),
+            ..SearchLimits::default()
+        };
+        let mut nodes = Vec::new();
+        tt.clear();
+        iterative_deepening(
+            &position,
+            &limits,
+            &SearchOptions::default(),
+            &tt,
+            &stop,
+            |iteration| nodes.push(iteration.nodes),
+        );
+        // Iterations go deeper until the budget is used up, and the one
+        // running out of it is aborted and not reported
+        assert!(!nodes.is_empty() && nodes.len() < 5, "{:?}", nodes);
+        assert!(
+            nodes.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            nodes
+        );
+        assert!(nodes.iter().all(|&count| count < 500), "{:?}", nodes);
+
+        stop.store(true, Ordering::Relaxed);
+        let mut depths = Vec::new();
+        iterative_deepening(
+            &position,
+            &SearchLimits::default(),
+            &SearchOptions::default(),
+            &tt,
+            &stop,
+            |iteration| depths.push(iteration.depth),
+        );
+        assert_eq!(depths, [1]);
+    }
+
+    #[test]
+    fn reuses_the_transposition_table() {
+        let position = Position::new();
+        let stop = AtomicBool::new(false);
+        let tt = TranspositionTable::new(1);
+        let mut nodes = Vec::new();
+        for _ in 0..2 {
+            iterative_deepening(
+                &position,
+                &SearchLimits::depth(3),
+                &SearchOptions::default(),
+                &tt,
+                &stop,
+                |iteration| nodes.push(iteration.nodes),
+            );
+        }
+        // The second search knows all positions from the first one
+        assert!(nodes[5] < nodes[2] / 2, "{:?}", nodes);
+    }
+
+    #[test]
+    fn stopping_in_the_middle_of_a_depth() {
+        let position = Position::new();
+        let start = std::time::Instant::now();
+        let handle = SearchHandle::spawn(move |stop| {
+            let mut depth = 0;
+            let limits = SearchLimits {
+                infinite: true,
+                ..SearchLimits::default()
+            };
+            let tt = TranspositionTable::new(1);
+            iterative_deepening(
+                &position,
+                &limits,
+                &SearchOptions::default(),
+                &tt,
+                stop,
+                |iteration| depth = iteration.depth,
+            )
+            .map(|result| (result, depth))
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        handle.stop();
+        let (result, depth) = handle.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(depth >= 1);
+        assert_eq!(result.pv.len(), depth);
+
+        // Without any time to think, still the first depth is searched
+        let position = Position::new();
+        let limits = SearchLimits {
+            movetime: Some(Duration::ZERO),
+            ..SearchLimits::default()
+        };
+        let stop = AtomicBool::new(true);
+        let tt = TranspositionTable::new(1);
+        assert!(iterative_deepening(
+            &position,
+            &limits,
+            &SearchOptions::default(),
+            &tt,
+            &stop,
+            |_| {}
+        )
+        .is_some());
     }
 
+    #[cfg(feature = "bench")]
     #[bench]
     fn new_field(b: &mut test::Bencher) {
-        let board: Board = Board::new();
+        let position = Position::new();
         b.iter(|| {
-            let mov = calculate_best_move(&board, Color::White, 3);
+            let mov = calculate_best_move(&position, 3);
             assert!(mov.is_some());
             test::black_box(mov);
             //panic!("{:?}", board);
@@ -1,62 +1,574 @@
 use serde::{Deserialize, Serialize};
 
-use super::figures::*;
+use super::{figures::*, zobrist};
 
 pub type Pos = (i8, i8); // Allow negatives to avoid underflow for values outside the board
 
 pub type Score = i32;
 
-#[derive(Serialize, Debug, Clone, Copy)]
+/// The name of a position in algebraic notation, e.g. "e4"
+pub fn square_name(pos: Pos) -> String {
+    let (x, y) = pos;
+    format!("{}{}", (b'a' + x as u8) as char, 8 - y)
+}
+
+/// Parses a position in algebraic notation, e.g. "e4"
+pub fn parse_square(name: &str) -> Option<Pos> {
+    match name.as_bytes() {
+        &[file @ b'a'..=b'h', rank @ b'1'..=b'8'] => {
+            Some(((file - b'a') as i8, 8 - (rank - b'0') as i8))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Move {
     pub from: Pos,
     pub to: Pos,
+    /// The figure a pawn reaching the last row turns into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub promotion: Option<Figure>,
+}
+
+impl Move {
+    pub const fn new(from: Pos, to: Pos) -> Self {
+        Self {
+            from,
+            to,
+            promotion: None,
+        }
+    }
+}
+
+/// The figures a pawn can be promoted to, the most valuable first
+pub const PROMOTIONS: [Figure; 4] =
+    [Figure::Queen, Figure::Rook, Figure::Bishop, Figure::Knight];
+
+pub type Cells = [[Option<Piece>; 8]; 8];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CastlingSide {
+    King,
+    Queen,
+}
+
+impl CastlingSide {
+    /// The column of the rook castling to this side
+    pub fn rook_column(self) -> i8 {
+        match self {
+            Self::King => 7,
+            Self::Queen => 0,
+        }
+    }
+
+    /// The column the king lands on when castling to this side
+    pub fn king_target_column(self) -> i8 {
+        match self {
+            Self::King => 6,
+            Self::Queen => 2,
+        }
+    }
+}
+
+/// Which castles are still allowed, i.e. the king and rook involved haven't
+/// moved yet
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct CastlingRights {
+    pub white_king_side: bool,
+    pub white_queen_side: bool,
+    pub black_king_side: bool,
+    pub black_queen_side: bool,
+}
+
+impl CastlingRights {
+    pub const ALL: Self = Self {
+        white_king_side: true,
+        white_queen_side: true,
+        black_king_side: true,
+        black_queen_side: true,
+    };
+
+    pub const NONE: Self = Self {
+        white_king_side: false,
+        white_queen_side: false,
+        black_king_side: false,
+        black_queen_side: false,
+    };
+
+    fn get_mut(&mut self, color: Color, side: CastlingSide) -> &mut bool {
+        match (color, side) {
+            (White, CastlingSide::King) => &mut self.white_king_side,
+            (White, CastlingSide::Queen) => &mut self.white_queen_side,
+            (Black, CastlingSide::King) => &mut self.black_king_side,
+            (Black, CastlingSide::Queen) => &mut self.black_queen_side,
+        }
+    }
+
+    pub fn get(mut self, color: Color, side: CastlingSide) -> bool {
+        *self.get_mut(color, side)
+    }
+
+    pub fn set(&mut self, color: Color, side: CastlingSide, allowed: bool) {
+        *self.get_mut(color, side) = allowed;
+    }
+}
+
+/// What is needed to take back a move with `Board::unmake`: the captured piece
+/// and the fields of the board that can't be recomputed from the move
+#[derive(Clone, Copy, Debug)]
+pub struct Undo {
+    captured: Option<Piece>,
+    castling: CastlingRights,
+    en_passant: Option<Pos>,
+    /// The hash before the move, e.g. to find repetitions
+    pub hash: u64,
+    pawn_hash: u64,
 }
 
+/// The king starts on the same column for both colors
+pub const KING_COLUMN: i8 = 4;
+
 #[derive(Clone, PartialEq, Debug, Deserialize)]
-pub struct Board(pub [[Option<Piece>; 8]; 8]);
+#[serde(from = "Cells")]
+pub struct Board {
+    pub cells: Cells,
+    pub castling: CastlingRights,
+    /// The position a pawn skipped with a double step in the last move, where
+    /// it can be hit en passant
+    pub en_passant: Option<Pos>,
+    /// The Zobrist hash of the pieces, castling rights and en passant
+    /// position. Kept up to date by `set` and `do_move`, but has to be
+    /// recomputed after changing the other fields directly
+    pub hash: u64,
+    /// The Zobrist hash of only the pawns, for caching what depends on the
+    /// pawn structure alone. Kept up to date like `hash`
+    pub pawn_hash: u64,
+}
+
+impl From<Cells> for Board {
+    /// Only the pieces are known, so assume that castling is allowed wherever
+    /// king and rook are still on their original positions, even if they
+    /// moved away and back, and that no pawn can be hit en passant. Games
+    /// that need these rules have to be given with their moves instead
+    fn from(cells: Cells) -> Self {
+        let mut board = Self {
+            cells,
+            castling: CastlingRights::NONE,
+            en_passant: None,
+            hash: 0,
+            pawn_hash: 0,
+        };
+        for color in [White, Black] {
+            let row = color.back_row();
+            if board.get((KING_COLUMN, row)) != Some(Piece::new(color, King)) {
+                continue;
+            }
+            for side in [CastlingSide::King, CastlingSide::Queen] {
+                let rook = (side.rook_column(), row);
+                if board.get(rook) == Some(Piece::new(color, Rook)) {
+                    board.castling.set(color, side, true);
+                }
+            }
+        }
+        board.hash = board.compute_hash();
+        board.pawn_hash = board.compute_pawn_hash();
+        board
+    }
+}
 
 impl Board {
     #[allow(dead_code)]
     pub fn empty() -> Self {
-        Self([[None; 8]; 8])
+        Self {
+            cells: [[None; 8]; 8],
+            castling: CastlingRights::NONE,
+            en_passant: None,
+            hash: 0,
+            pawn_hash: 0,
+        }
     }
 
     #[allow(dead_code)]
     pub fn new() -> Self {
-        DEFAULT_FIELD
+        let mut board = DEFAULT_FIELD;
+        board.hash = board.compute_hash();
+        board.pawn_hash = board.compute_pawn_hash();
+        board
+    }
+
+    /// The Zobrist hash computed from scratch instead of updated with every
+    /// change
+    pub fn compute_hash(&self) -> u64 {
+        let mut hash = zobrist::castling(self.castling)
+            ^ zobrist::en_passant(self.capturable_en_passant());
+        for x in 0..8 {
+            for y in 0..8 {
+                if let Some(piece) = self.get((x, y)) {
+                    hash ^= zobrist::piece(piece, (x, y));
+                }
+            }
+        }
+        hash
+    }
+
+    /// Like `compute_hash`, but for `pawn_hash`
+    pub fn compute_pawn_hash(&self) -> u64 {
+        let mut hash = 0;
+        for x in 0..8 {
+            for y in 0..8 {
+                if let Some(piece) = self.get((x, y)) {
+                    if piece.figure == Pawn {
+                        hash ^= zobrist::piece(piece, (x, y));
+                    }
+                }
+            }
+        }
+        hash
     }
 
     pub fn get(&self, pos: Pos) -> Option<Piece> {
         let (x, y) = pos;
-        self.0[x as usize][y as usize]
+        self.cells[x as usize][y as usize]
     }
 
-    pub fn do_move(&mut self, mov: Move) {
+    pub fn set(&mut self, pos: Pos, piece: Option<Piece>) {
+        let (x, y) = pos;
+        let cell = &mut self.cells[x as usize][y as usize];
+        for piece in [*cell, piece].iter().flatten() {
+            self.hash ^= zobrist::piece(*piece, pos);
+            if piece.figure == Pawn {
+                self.pawn_hash ^= zobrist::piece(*piece, pos);
+            }
+        }
+        *cell = piece;
+    }
+
+    /// Removes the piece at `pos` and returns it
+    fn take(&mut self, pos: Pos) -> Option<Piece> {
+        let piece = self.get(pos);
+        self.set(pos, None);
+        piece
+    }
+
+    /// Plays `mov` and returns what `unmake` needs to take it back
+    pub fn do_move(&mut self, mov: Move) -> Undo {
         debug_assert!(self.get(mov.from).is_some(), "Bad move: {:?}", mov);
-        let mut figure = self.0[mov.from.0 as usize][mov.from.1 as usize]
-            .take()
-            .unwrap(); // Unwrapping fails if the `from` position was empty
+        let mut undo = Undo {
+            captured: self.get(mov.to),
+            castling: self.castling,
+            en_passant: self.en_passant,
+            hash: self.hash,
+            pawn_hash: self.pawn_hash,
+        };
+        // The castling rights and en passant position are hashed as a whole,
+        // and whether en passant is hashed depends on the pieces around it
+        self.hash ^= zobrist::castling(self.castling)
+            ^ zobrist::en_passant(self.capturable_en_passant());
+        if self.is_en_passant(mov) {
+            // The hit pawn is next to the pawn's original position
+            undo.captured = self.take((mov.to.0, mov.from.1));
+        }
+        // Unwrapping fails if the `from` position was empty
+        let mut figure = self.take(mov.from).unwrap();
         let color = figure.color;
-        // Transfrom pawns on the to queens
-        if figure.figure == Figure::Pawn {
-            if color == Color::White && mov.to.1 == 0 {
-                figure = Piece {
-                    color,
-                    figure: Figure::Queen,
-                };
-            } else if color == Color::Black && mov.to.1 == 7 {
-                figure = Piece {
-                    color,
-                    figure: Figure::Queen,
-                };
+        self.en_passant = None;
+        if figure.figure == Pawn && (mov.to.1 - mov.from.1).abs() == 2 {
+            self.en_passant = Some((mov.from.0, (mov.from.1 + mov.to.1) / 2));
+        }
+        // Moving the king or a rook or losing a rook ends castling rights
+        if figure.figure == King {
+            self.castling.set(color, CastlingSide::King, false);
+            self.castling.set(color, CastlingSide::Queen, false);
+        }
+        for pos in [mov.from, mov.to] {
+            for color in [White, Black] {
+                for side in [CastlingSide::King, CastlingSide::Queen] {
+                    if pos == (side.rook_column(), color.back_row()) {
+                        self.castling.set(color, side, false);
+                    }
+                }
             }
         }
-        self.0[mov.to.0 as usize][mov.to.1 as usize] = Some(figure);
+        // Castling is encoded as a king move by two columns
+        if let Some((rook, passed)) = self.castling_rook(mov, figure) {
+            let rook = self.take(rook);
+            debug_assert_eq!(rook, Some(Piece::new(color, Rook)));
+            self.set(passed, rook);
+        }
+        // Transform pawns on the last row, into queens unless chosen otherwise
+        if figure.figure == Figure::Pawn
+            && mov.to.1 == color.flipped().back_row()
+        {
+            figure = Piece {
+                color,
+                figure: mov.promotion.unwrap_or(Figure::Queen),
+            };
+        }
+        self.set(mov.to, Some(figure));
+        self.hash ^= zobrist::castling(self.castling)
+            ^ zobrist::en_passant(self.capturable_en_passant());
+        undo
+    }
+
+    /// Takes back `mov`, which has to be the last move played with `do_move`.
+    /// A promotion is only recognized if `mov` names the promoted figure, as
+    /// all generated moves do
+    pub fn unmake(&mut self, mov: Move, undo: Undo) {
+        // Unwrapping fails if the `to` position was empty
+        let mut figure = self.take(mov.to).unwrap();
+        if mov.promotion.is_some() {
+            figure.figure = Pawn;
+        }
+        if let Some((rook, passed)) = self.castling_rook(mov, figure) {
+            let piece = self.take(passed);
+            self.set(rook, piece);
+        }
+        self.set(mov.from, Some(figure));
+        let is_en_passant = figure.figure == Pawn
+            && mov.from.0 != mov.to.0
+            && Some(mov.to) == undo.en_passant;
+        if is_en_passant {
+            self.set((mov.to.0, mov.from.1), undo.captured);
+        } else {
+            self.set(mov.to, undo.captured);
+        }
+        self.castling = undo.castling;
+        self.en_passant = undo.en_passant;
+        self.hash = undo.hash;
+        self.pawn_hash = undo.pawn_hash;
+    }
+
+    /// If `mov` of `figure` is a castling, returns where the rook starts and
+    /// the position the king passes, which is where the rook ends up
+    fn castling_rook(&self, mov: Move, figure: Piece) -> Option<(Pos, Pos)> {
+        if figure.figure != King || (mov.to.0 - mov.from.0).abs() != 2 {
+            return None;
+        }
+        let side = if mov.to.0 > mov.from.0 {
+            CastlingSide::King
+        } else {
+            CastlingSide::Queen
+        };
+        let row = mov.from.1;
+        Some((
+            (side.rook_column(), row),
+            ((mov.from.0 + mov.to.0) / 2, row),
+        ))
     }
 
     pub fn is_inside(&self, pos: Pos) -> bool {
         let (x, y) = pos;
-        x >= 0 && x < 8 && y >= 0 && y < 8
+        (0..8).contains(&x) && (0..8).contains(&y)
+    }
+
+    pub fn king_position(&self, color: Color) -> Option<Pos> {
+        (0..8)
+            .flat_map(|x| (0..8).map(move |y| (x, y)))
+            .find(|&pos| self.get(pos) == Some(Piece::new(color, King)))
+    }
+
+    /// Calls `insert_into` with the position of every piece of color `by`
+    /// that attacks `pos`
+    pub fn attackers(
+        &self,
+        pos: Pos,
+        by: Color,
+        mut insert_into: impl FnMut(Pos),
+    ) {
+        let is = |pos: Pos, figures: &[Figure]| {
+            if !self.is_inside(pos) {
+                return false;
+            }
+            match self.get(pos) {
+                Some(piece) => {
+                    piece.color == by && figures.contains(&piece.figure)
+                }
+                None => false,
+            }
+        };
+        // Pawns hit diagonally forwards, so look one row behind `pos`
+        for dx in [-1, 1] {
+            let from = (pos.0 + dx, pos.1 - by.forwards());
+            if is(from, &[Pawn]) {
+                insert_into(from);
+            }
+        }
+        for (dx, dy) in KNIGHT_JUMPS {
+            let from = (pos.0 + dx, pos.1 + dy);
+            if is(from, &[Knight]) {
+                insert_into(from);
+            }
+        }
+        for (dx, dy) in KING_STEPS {
+            let from = (pos.0 + dx, pos.1 + dy);
+            if is(from, &[King]) {
+                insert_into(from);
+            }
+        }
+        for (directions, sliders) in [
+            (ROOK_DIRECTIONS, [Rook, Queen]),
+            (BISHOP_DIRECTIONS, [Bishop, Queen]),
+        ] {
+            for (dx, dy) in directions {
+                if let Some(from) = self.first_piece_in_line(pos, (dx, dy)) {
+                    if is(from, &sliders) {
+                        insert_into(from);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn is_attacked(&self, pos: Pos, by: Color) -> bool {
+        let mut attacked = false;
+        self.attackers(pos, by, |_| attacked = true);
+        attacked
+    }
+
+    pub fn is_in_check(&self, color: Color) -> bool {
+        self.king_position(color)
+            .is_some_and(|king| self.is_attacked(king, color.flipped()))
+    }
+
+    /// Returns the number of pieces giving check to the king of `color` and
+    /// the positions of all pieces of `color` that are pinned to their king
+    pub fn checks_and_pins(&self, color: Color) -> (usize, Vec<Pos>) {
+        let king = match self.king_position(color) {
+            Some(king) => king,
+            None => return (0, Vec::new()),
+        };
+        let mut checkers = 0;
+        self.attackers(king, color.flipped(), |_| checkers += 1);
+        let mut pinned = Vec::new();
+        for (directions, sliders) in [
+            (ROOK_DIRECTIONS, [Rook, Queen]),
+            (BISHOP_DIRECTIONS, [Bishop, Queen]),
+        ] {
+            for direction in directions {
+                // An allied piece is pinned if the next piece behind it
+                // along the line is an enemy slider moving along that line
+                let blocker = match self.first_piece_in_line(king, direction) {
+                    Some(pos) if self.get(pos).unwrap().color == color => pos,
+                    _ => continue,
+                };
+                if let Some(pinner) =
+                    self.first_piece_in_line(blocker, direction)
+                {
+                    let piece = self.get(pinner).unwrap();
+                    if piece.color != color && sliders.contains(&piece.figure) {
+                        pinned.push(blocker);
+                    }
+                }
+            }
+        }
+        (checkers, pinned)
+    }
+
+    /// Returns the position of the first piece seen when walking from `pos`
+    /// into `direction`, not including `pos` itself
+    fn first_piece_in_line(
+        &self,
+        pos: Pos,
+        direction: (i8, i8),
+    ) -> Option<Pos> {
+        let (mut x, mut y) = pos;
+        loop {
+            x += direction.0;
+            y += direction.1;
+            if !self.is_inside((x, y)) {
+                return None;
+            }
+            if self.get((x, y)).is_some() {
+                return Some((x, y));
+            }
+        }
+    }
+
+    /// Calls `insert_into` with all castling moves of `color` whose king
+    /// doesn't start on or pass through an attacked position
+    pub fn castling_moves(
+        &self,
+        color: Color,
+        mut insert_into: impl FnMut(Move),
+    ) {
+        let row = color.back_row();
+        let king = (KING_COLUMN, row);
+        for side in [CastlingSide::King, CastlingSide::Queen] {
+            if !self.castling.get(color, side) {
+                continue;
+            }
+            debug_assert_eq!(self.get(king), Some(Piece::new(color, King)));
+            let rook_column = side.rook_column();
+            let between =
+                rook_column.min(KING_COLUMN) + 1..rook_column.max(KING_COLUMN);
+            if between.into_iter().any(|x| self.get((x, row)).is_some()) {
+                continue;
+            }
+            // The king may not castle out of or through check. Whether it
+            // ends up in check is left to the legality check
+            let target = side.king_target_column();
+            let passed = (KING_COLUMN + target) / 2;
+            if [KING_COLUMN, passed]
+                .iter()
+                .any(|&x| self.is_attacked((x, row), color.flipped()))
+            {
+                continue;
+            }
+            insert_into(Move::new(king, (target, row)));
+        }
+    }
+
+    /// Whether neither side can ever checkmate, that is only kings and at
+    /// most one knight or any number of bishops on fields of one color remain
+    pub fn has_insufficient_material(&self) -> bool {
+        let mut minors = Vec::new();
+        for x in 0..8 {
+            for y in 0..8 {
+                match self.get((x, y)) {
+                    None | Some(Piece { figure: King, .. }) => {}
+                    Some(Piece {
+                        figure: figure @ (Bishop | Knight),
+                        ..
+                    }) => minors.push((figure, (x + y) % 2)),
+                    Some(_) => return false,
+                }
+            }
+        }
+        match minors.as_slice() {
+            [] | [_] => true,
+            [(_, field_color), ..] => {
+                minors.iter().all(|&minor| minor == (Bishop, *field_color))
+            }
+        }
+    }
+
+    /// The en passant position if a pawn stands next to the one that skipped
+    /// it and could hit it, ignoring pins. Only then can it make a difference
+    /// for the position, so only then it's part of the hash
+    pub fn capturable_en_passant(&self) -> Option<Pos> {
+        let (x, y) = self.en_passant?;
+        let color = if y > 3 { White } else { Black };
+        let row = y + color.forwards();
+        let hitter = Some(Piece::new(color.flipped(), Pawn));
+        [x - 1, x + 1]
+            .iter()
+            .any(|&x| self.is_inside((x, row)) && self.get((x, row)) == hitter)
+            .then_some((x, y))
+    }
+
+    pub fn is_en_passant(&self, mov: Move) -> bool {
+        Some(mov.to) == self.en_passant
+            && mov.from.0 != mov.to.0
+            && self.get(mov.from).map(|piece| piece.figure) == Some(Pawn)
+    }
+
+    /// Returns whether the king of the moving color is safe after `mov`
+    pub fn is_legal_after(&self, mov: Move) -> bool {
+        let color = self.get(mov.from).expect("Bad move").color;
+        let mut copy = self.clone();
+        copy.do_move(mov);
+        !copy.is_in_check(color)
     }
 }
 
@@ -65,5 +577,5 @@ use Figure::*;
 
 #[allow(unused)]
 #[rustfmt::skip]
-const DEFAULT_FIELD: Board = Board([[Some(Piece { color: Black, figure: Rook }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Rook })], [Some(Piece { color: Black, figure: Knight }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Knight })], [Some(Piece { color: Black, figure: Bishop }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Bishop })], [Some(Piece { color: Black, figure: Queen }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Queen })], [Some(Piece { color: Black, figure: King }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: King })], [Some(Piece { color: Black, figure: Bishop }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Bishop })], [Some(Piece { color: Black, figure: Knight }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Knight })], [Some(Piece { color: Black, figure: Rook }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Rook })]])
+const DEFAULT_FIELD: Board = Board { castling: CastlingRights::ALL, en_passant: None, hash: 0, pawn_hash: 0, cells: [[Some(Piece { color: Black, figure: Rook }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Rook })], [Some(Piece { color: Black, figure: Knight }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Knight })], [Some(Piece { color: Black, figure: Bishop }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Bishop })], [Some(Piece { color: Black, figure: Queen }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Queen })], [Some(Piece { color: Black, figure: King }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: King })], [Some(Piece { color: Black, figure: Bishop }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Bishop })], [Some(Piece { color: Black, figure: Knight }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Knight })], [Some(Piece { color: Black, figure: Rook }), Some(Piece { color: Black, figure: Pawn }), None, None, None, None, Some(Piece { color: White, figure: Pawn }), Some(Piece { color: White, figure: Rook })]] }
 ;
@@ -0,0 +1,553 @@
+//! The evaluation of positions without searching: Material, where the pieces
+//! stand, by piece-square tables, the pawn structure, how mobile the pieces
+//! are and how safe the kings are. All have a midgame and an endgame value,
+//! which are blended by how much material is left (tapered evaluation), e.g.
+//! the king should hide in the midgame but be active in the endgame
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{board::*, figures::*, pawns::PawnTable};
+
+/// The score of a single pawn in the middlegame. Scores are in centipawns
+pub const PAWN_SCORE: Score = 100;
+
+/// A value for each position, as seen by white with the 8th rank first. It's
+/// indexed by `[row][column]`, the reverse of `Pos` and `Board::cells`. Black
+/// uses the mirrored table
+type Table = [[Score; 8]; 8];
+
+/// The phase of the starting position, where only the midgame counts. Each
+/// knight and bishop adds 1, each rook 2 and each queen 4
+const MIDGAME_PHASE: Score = 24;
+
+/// Material of each figure, in the order of their discriminants. The king
+/// can't be traded and counts nothing
+const MIDGAME_VALUES: [Score; 6] = [100, 330, 320, 500, 900, 0];
+const ENDGAME_VALUES: [Score; 6] = [120, 330, 300, 520, 940, 0];
+
+#[rustfmt::skip]
+const PAWN_MIDGAME: Table = [
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [ 50,  50,  50,  50,  50,  50,  50,  50],
+    [ 10,  10,  20,  30,  30,  20,  10,  10],
+    [  5,   5,  10,  25,  25,  10,   5,   5],
+    [  0,   0,   0,  20,  20,   0,   0,   0],
+    [  5,  -5, -10,   0,   0, -10,  -5,   5],
+    [  5,  10,  10, -20, -20,  10,  10,   5],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+];
+
+/// Passed pawns decide endgames, so advancing counts most
+#[rustfmt::skip]
+const PAWN_ENDGAME: Table = [
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [ 80,  80,  80,  80,  80,  80,  80,  80],
+    [ 50,  50,  50,  50,  50,  50,  50,  50],
+    [ 30,  30,  30,  30,  30,  30,  30,  30],
+    [ 15,  15,  15,  15,  15,  15,  15,  15],
+    [  5,   5,   5,   5,   5,   5,   5,   5],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+];
+
+#[rustfmt::skip]
+const KNIGHT: Table = [
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+    [-40, -20,   0,   0,   0,   0, -20, -40],
+    [-30,   0,  10,  15,  15,  10,   0, -30],
+    [-30,   5,  15,  20,  20,  15,   5, -30],
+    [-30,   0,  15,  20,  20,  15,   0, -30],
+    [-30,   5,  10,  15,  15,  10,   5, -30],
+    [-40, -20,   0,   5,   5,   0, -20, -40],
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+];
+
+#[rustfmt::skip]
+const BISHOP: Table = [
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+    [-10,   0,   0,   0,   0,   0,   0, -10],
+    [-10,   0,   5,  10,  10,   5,   0, -10],
+    [-10,   5,   5,  10,  10,   5,   5, -10],
+    [-10,   0,  10,  10,  10,  10,   0, -10],
+    [-10,  10,  10,  10,  10,  10,  10, -10],
+    [-10,   5,   0,   0,   0,   0,   5, -10],
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+];
+
+#[rustfmt::skip]
+const ROOK: Table = [
+    [  0,   0,   0,   0,   0,   0,   0,   0],
+    [  5,  10,  10,  10,  10,  10,  10,   5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [ -5,   0,   0,   0,   0,   0,   0,  -5],
+    [  0,   0,   0,   5,   5,   0,   0,   0],
+];
+
+#[rustfmt::skip]
+const QUEEN: Table = [
+    [-20, -10, -10,  -5,  -5, -10, -10, -20],
+    [-10,   0,   0,   0,   0,   0,   0, -10],
+    [-10,   0,   5,   5,   5,   5,   0, -10],
+    [ -5,   0,   5,   5,   5,   5,   0,  -5],
+    [  0,   0,   5,   5,   5,   5,   0,  -5],
+    [-10,   5,   5,   5,   5,   5,   0, -10],
+    [-10,   0,   5,   0,   0,   0,   0, -10],
+    [-20, -10, -10,  -5,  -5, -10, -10, -20],
+];
+
+/// Behind the own pawns, after castling
+#[rustfmt::skip]
+const KING_MIDGAME: Table = [
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-20, -30, -30, -40, -40, -30, -30, -20],
+    [-10, -20, -20, -20, -20, -20, -20, -10],
+    [ 20,  20,   0,   0,   0,   0,  20,  20],
+    [ 20,  30,  10,   0,   0,  10,  30,  20],
+];
+
+/// In the center, where it supports the pawns and is hard to mate
+#[rustfmt::skip]
+const KING_ENDGAME: Table = [
+    [-50, -40, -30, -20, -20, -30, -40, -50],
+    [-30, -20, -10,   0,   0, -10, -20, -30],
+    [-30, -10,  20,  30,  30,  20, -10, -30],
+    [-30, -10,  30,  40,  40,  30, -10, -30],
+    [-30, -10,  30,  40,  40,  30, -10, -30],
+    [-30, -10,  20,  30,  30,  20, -10, -30],
+    [-30, -30,   0,   0,   0,   0, -30, -30],
+    [-50, -30, -30, -30, -30, -30, -30, -50],
+];
+
+/// The tables of each figure, in the order of their discriminants
+const MIDGAME_TABLES: [&Table; 6] = [
+    &PAWN_MIDGAME,
+    &BISHOP,
+    &KNIGHT,
+    &ROOK,
+    &QUEEN,
+    &KING_MIDGAME,
+];
+const ENDGAME_TABLES: [&Table; 6] = [
+    &PAWN_ENDGAME,
+    &BISHOP,
+    &KNIGHT,
+    &ROOK,
+    &QUEEN,
+    &KING_ENDGAME,
+];
+
+/// For each position a piece reaches, as (midgame, endgame), in the order of
+/// the discriminants of the figures. Pawns and kings aren't counted
+const MOBILITY: [(Score, Score); 6] =
+    [(0, 0), (5, 5), (4, 4), (2, 4), (1, 2), (0, 0)];
+/// The number of positions a piece usually reaches, which scores 0
+const USUAL_MOBILITY: [Score; 6] = [0, 7, 4, 7, 14, 0];
+
+/// For each pawn right in front of the king or next to that position. Like
+/// all king safety terms, this only counts in the midgame
+const PAWN_SHIELD: Score = 15;
+/// For each pawn one row further, if there is none right in front
+const DISTANT_PAWN_SHIELD: Score = 8;
+/// For each column next to or of the king without pawns of its color
+const HALF_OPEN_COLUMN_NEAR_KING: Score = -15;
+/// Added for each of these columns without any pawns
+const OPEN_COLUMN_NEAR_KING: Score = -10;
+
+/// How much a piece attacking the positions around the opposing king
+/// threatens it, in the order of the discriminants of the figures
+const KING_ATTACK_WEIGHT: [Score; 6] = [0, 20, 20, 40, 80, 0];
+/// The percentage of the attack weight that counts, by the number of pieces
+/// attacking. A single piece can't do much on its own
+const KING_ATTACK_SCALE: [Score; 8] = [0, 0, 50, 75, 88, 94, 97, 99];
+
+/// The material value of a figure in the middlegame. The king can't be
+/// traded, but counts as the most valuable piece, e.g. so that it is the last
+/// to capture in an exchange
+pub fn value(figure: Figure) -> Score {
+    match figure {
+        Figure::King => 100 * PAWN_SCORE,
+        _ => MIDGAME_VALUES[figure as usize],
+    }
+}
+
+/// How far the game is from the endgame, from `MIDGAME_PHASE` with all
+/// pieces on the board down to 0 with only kings and pawns
+fn phase(board: &Board) -> Score {
+    let phase = board
+        .cells
+        .iter()
+        .flatten()
+        .flatten()
+        .map(|piece| match piece.figure {
+            Figure::Knight | Figure::Bishop => 1,
+            Figure::Rook => 2,
+            Figure::Queen => 4,
+            Figure::Pawn | Figure::King => 0,
+        })
+        .sum::<Score>();
+    // Promotions can add more than the starting position has
+    phase.min(MIDGAME_PHASE)
+}
+
+/// What the pieces of a color can do
+struct Activity {
+    /// As (midgame, endgame)
+    mobility: (Score, Score),
+    /// The threat to the opposing king, only counting in the midgame
+    king_attack: Score,
+}
+
+/// Counts the positions the knights, bishops, rooks and queens of `color`
+/// reach, and how many of them attack the positions around the opposing king
+fn activity(board: &Board, color: Color) -> Activity {
+    let king = board.king_position(color.flipped());
+    // The king and the positions next to it
+    let in_king_zone = |pos: Pos| {
+        king.is_some_and(|king| {
+            (pos.0 - king.0).abs() <= 1 && (pos.1 - king.1).abs() <= 1
+        })
+    };
+    let mut mobility = (0, 0);
+    let mut attackers = 0;
+    let mut attack_weight = 0;
+    for x in 0..8 {
+        for y in 0..8 {
+            let piece = match board.get((x, y)) {
+                Some(piece) if piece.color == color => piece,
+                _ => continue,
+            };
+            let figure = piece.figure as usize;
+            if MOBILITY[figure] == (0, 0) {
+                continue;
+            }
+            let mut reached = 0;
+            let mut attacks_king = false;
+            piece.reachables((x, y), board, |pos| {
+                reached += 1;
+                attacks_king |= in_king_zone(pos);
+            });
+            let (midgame, endgame) = MOBILITY[figure];
+            mobility.0 += midgame * (reached - USUAL_MOBILITY[figure]);
+            mobility.1 += endgame * (reached - USUAL_MOBILITY[figure]);
+            if attacks_king {
+                attackers += 1;
+                attack_weight += KING_ATTACK_WEIGHT[figure];
+            }
+        }
+    }
+    Activity {
+        mobility,
+        king_attack: attack_weight * KING_ATTACK_SCALE[attackers.min(7)] / 100,
+    }
+}
+
+/// How well the pawns in front of the king of `color` protect it, only
+/// counting in the midgame
+fn king_shelter(board: &Board, color: Color) -> Score {
+    let king = match board.king_position(color) {
+        Some(king) => king,
+        None => return 0,
+    };
+    let forwards = color.forwards();
+    let pawn = |x: i8, y: i8, color: Color| {
+        board.is_inside((x, y))
+            && board.get((x, y)) == Some(Piece::new(color, Figure::Pawn))
+    };
+    let mut shelter = 0;
+    for x in king.0 - 1..=king.0 + 1 {
+        if !board.is_inside((x, king.1)) {
+            continue;
+        }
+        if pawn(x, king.1 + forwards, color) {
+            shelter += PAWN_SHIELD;
+        } else if pawn(x, king.1 + 2 * forwards, color) {
+            shelter += DISTANT_PAWN_SHIELD;
+        }
+        let own_pawns = (0..8).any(|y| pawn(x, y, color));
+        let other_pawns = (0..8).any(|y| pawn(x, y, color.flipped()));
+        if !own_pawns {
+            shelter += HALF_OPEN_COLUMN_NEAR_KING;
+            if !other_pawns {
+                shelter += OPEN_COLUMN_NEAR_KING;
+            }
+        }
+    }
+    shelter
+}
+
+/// A value for the midgame and one for the endgame
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize)]
+pub struct Phased {
+    pub midgame: Score,
+    pub endgame: Score,
+}
+
+impl Phased {
+    fn add(&mut self, (midgame, endgame): (Score, Score)) {
+        self.midgame += midgame;
+        self.endgame += endgame;
+    }
+}
+
+/// The terms of the evaluation for one color, each positive if it is good for
+/// that color
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize)]
+pub struct Terms {
+    pub material: Phased,
+    pub piece_squares: Phased,
+    pub pawns: Phased,
+    pub mobility: Phased,
+    /// The shelter of the king minus the threat of the opposing pieces
+    pub king_safety: Phased,
+}
+
+impl Terms {
+    /// The terms with their names, in the order they are shown
+    fn named(&self) -> [(&'static str, Phased); 5] {
+        [
+            ("Material", self.material),
+            ("Piece-square", self.piece_squares),
+            ("Pawns", self.pawns),
+            ("Mobility", self.mobility),
+            ("King safety", self.king_safety),
+        ]
+    }
+
+    pub fn total(&self) -> Phased {
+        let mut total = Phased::default();
+        for (_, term) in IntoIterator::into_iter(self.named()) {
+            total.add((term.midgame, term.endgame));
+        }
+        total
+    }
+}
+
+/// Every term `evaluate` adds up, to see why a position scores the way it
+/// does
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+pub struct Trace {
+    pub white: Terms,
+    pub black: Terms,
+    /// From `MIDGAME_PHASE`, where only the midgame values count, down to 0,
+    /// where only the endgame values do
+    pub phase: Score,
+    /// The score of `evaluate`, positive if it is good for white
+    pub score: Score,
+}
+
+/// A table with a row for each term and a column for each color and phase,
+/// followed by the phase and the score
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let row =
+            |f: &mut fmt::Formatter, name, white: Phased, black: Phased| {
+                writeln!(
+                    f,
+                    "{:<14}{:>9}{:>9}{:>9}{:>9}",
+                    name,
+                    white.midgame,
+                    white.endgame,
+                    black.midgame,
+                    black.endgame
+                )
+            };
+        writeln!(
+            f,
+            "{:<14}{:>9}{:>9}{:>9}{:>9}",
+            "Term", "White MG", "White EG", "Black MG", "Black EG"
+        )?;
+        let white = IntoIterator::into_iter(self.white.named());
+        for ((name, white), (_, black)) in white.zip(self.black.named()) {
+            row(f, name, white, black)?;
+        }
+        row(f, "Total", self.white.total(), self.black.total())?;
+        writeln!(f, "Phase {}/{}", self.phase, MIDGAME_PHASE)?;
+        write!(f, "Score {} (white's view)", self.score)
+    }
+}
+
+/// The score of `board`, positive if it is good for white and negative if it
+/// is good for black. The pawn structure is looked up in `pawns`
+pub fn evaluate(board: &Board, pawns: &mut PawnTable) -> Score {
+    evaluate_trace(board, pawns).score
+}
+
+/// Evaluates `board` like `evaluate`, keeping every term apart
+pub fn evaluate_trace(board: &Board, pawns: &mut PawnTable) -> Trace {
+    let mut terms = [Terms::default(); 2];
+    let pawn_scores = pawns.evaluate(board);
+    for color in [Color::White, Color::Black] {
+        let own = &mut terms[color.index()];
+        own.pawns.add(pawn_scores[color.index()]);
+        let activity = activity(board, color);
+        own.mobility.add(activity.mobility);
+        own.king_safety.add((king_shelter(board, color), 0));
+        let opponent = &mut terms[color.flipped().index()];
+        opponent.king_safety.add((-activity.king_attack, 0));
+    }
+    for x in 0..8 {
+        for y in 0..8 {
+            let piece = match board.cells[x][y] {
+                Some(piece) => piece,
+                None => continue,
+            };
+            let figure = piece.figure as usize;
+            let row = match piece.color {
+                Color::White => y,
+                Color::Black => 7 - y,
+            };
+            let own = &mut terms[piece.color.index()];
+            own.material
+                .add((MIDGAME_VALUES[figure], ENDGAME_VALUES[figure]));
+            own.piece_squares.add((
+                MIDGAME_TABLES[figure][row][x],
+                ENDGAME_TABLES[figure][row][x],
+            ));
+        }
+    }
+    let [white, black] = terms;
+    let (white_total, black_total) = (white.total(), black.total());
+    let midgame = white_total.midgame - black_total.midgame;
+    let endgame = white_total.endgame - black_total.endgame;
+    let phase = phase(board);
+    Trace {
+        white,
+        black,
+        phase,
+        score: (midgame * phase + endgame * (MIDGAME_PHASE - phase))
+            / MIDGAME_PHASE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Position;
+
+    /// The board upside down with the colors swapped
+    fn mirrored(board: &Board) -> Board {
+        let mut mirrored = Board::empty();
+        for x in 0..8 {
+            for y in 0..8 {
+                mirrored.cells[x][7 - y] = board.cells[x][y].map(|piece| {
+                    Piece::new(piece.color.flipped(), piece.figure)
+                });
+            }
+        }
+        mirrored.pawn_hash = mirrored.compute_pawn_hash();
+        mirrored
+    }
+
+    #[test]
+    fn mirroring_negates_the_score() {
+        let mut pawns = PawnTable::new();
+        assert_eq!(evaluate(&Board::new(), &mut pawns), 0);
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "6k1/5ppp/8/8/8/8/r4PPP/1R4K1 w - - 0 1",
+        ] {
+            let board = Position::from_fen(fen).unwrap().board;
+            let score = evaluate(&board, &mut pawns);
+            assert_ne!(score, 0, "{}", fen);
+            assert_eq!(evaluate(&mirrored(&board), &mut pawns), -score, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn trace_splits_the_terms_by_color() {
+        let board = Position::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .unwrap()
+        .board;
+        let mut pawns = PawnTable::new();
+        let trace = evaluate_trace(&board, &mut pawns);
+        assert_eq!(trace.score, evaluate(&board, &mut pawns));
+        assert_eq!(trace.phase, MIDGAME_PHASE);
+        // Both sides have all their pieces and 8 pawns
+        assert_eq!(trace.white.material, trace.black.material);
+        let mirrored = evaluate_trace(&mirrored(&board), &mut pawns);
+        assert_eq!(
+            (mirrored.white, mirrored.black),
+            (trace.black, trace.white)
+        );
+        let text = trace.to_string();
+        assert!(text.lines().any(|line| line.starts_with("King safety ")));
+        assert!(
+            text.ends_with(&format!("Score {} (white's view)", trace.score))
+        );
+    }
+
+    #[test]
+    fn kings_move_to_the_center_in_the_endgame() {
+        let score = |fen| {
+            let board = Position::from_fen(fen).unwrap().board;
+            evaluate(&board, &mut PawnTable::new())
+        };
+        // With all pieces on, the king is safer on g1
+        assert!(
+            score("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQ1RK1 w kq - 0 1")
+                > score(
+                    "rnbqkbnr/pppppppp/8/8/8/4K3/PPPPPPPP/RNBQ1R2 w kq - 0 1"
+                )
+        );
+        // Without any pieces, it belongs in the center
+        assert!(
+            score("4k3/pppppppp/8/8/8/8/PPPPPPPP/6K1 w - - 0 1")
+                < score("4k3/pppppppp/8/8/8/4K3/PPPPPPPP/8 w - - 0 1")
+        );
+    }
+
+    #[test]
+    fn mobile_pieces_score_higher() {
+        let board = |fen| Position::from_fen(fen).unwrap().board;
+        // The bishop on d4 reaches 13 positions, the one on h1 none
+        let center = board("4k3/8/8/8/3B4/8/6P1/4K3 w - - 0 1");
+        let corner = board("4k3/8/8/8/8/8/6P1/4K2B w - - 0 1");
+        assert_eq!(
+            activity(&center, Color::White).mobility.0
+                - activity(&corner, Color::White).mobility.0,
+            13 * MOBILITY[Figure::Bishop as usize].0
+        );
+    }
+
+    #[test]
+    fn kings_need_shelter() {
+        let board = |fen| Position::from_fen(fen).unwrap().board;
+        let castled = board("4k3/8/8/8/8/8/5PPP/6K1 w - - 0 1");
+        let advanced = board("4k3/8/8/8/8/5PPP/8/6K1 w - - 0 1");
+        let open = board("4k3/8/8/8/8/8/5P1P/6K1 w - - 0 1");
+        assert_eq!(king_shelter(&castled, Color::White), 3 * PAWN_SHIELD);
+        assert_eq!(
+            king_shelter(&advanced, Color::White),
+            3 * DISTANT_PAWN_SHIELD
+        );
+        assert_eq!(
+            king_shelter(&open, Color::White),
+            2 * PAWN_SHIELD
+                + HALF_OPEN_COLUMN_NEAR_KING
+                + OPEN_COLUMN_NEAR_KING
+        );
+        // A lone queen attacking g7 isn't a threat yet, with a rook it is
+        let queen = board("6k1/5ppp/8/6Q1/8/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(activity(&queen, Color::White).king_attack, 0);
+        let attack = board("6k1/4Rppp/8/6Q1/8/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(
+            activity(&attack, Color::White).king_attack,
+            (KING_ATTACK_WEIGHT[Figure::Queen as usize]
+                + KING_ATTACK_WEIGHT[Figure::Rook as usize])
+                * KING_ATTACK_SCALE[2]
+                / 100
+        );
+    }
+}
@@ -0,0 +1,418 @@
+use std::fmt;
+
+use crate::{board::*, figures::*, position::Position};
+
+pub const START_FEN: &str =
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum FenError {
+    /// A FEN needs 4 to 6 space separated fields
+    FieldCount(usize),
+    /// The placement field must describe exactly 8 ranks
+    RankCount(usize),
+    /// A rank in the placement field doesn't describe exactly 8 fields
+    RankLength {
+        rank: u8,
+        length: usize,
+    },
+    UnknownPiece {
+        rank: u8,
+        piece: char,
+    },
+    /// Runs of empty fields are written as the digits 1 to 8
+    EmptyFieldCount {
+        rank: u8,
+        digit: char,
+    },
+    /// Each side needs exactly one king
+    KingCount {
+        color: Color,
+        count: usize,
+    },
+    /// Pawns can't stand on the first or last rank
+    PawnOnBackRank {
+        rank: u8,
+    },
+    SideToMove(String),
+    /// The side that just moved can't have left its king in check
+    OpponentInCheck,
+    Castling(String),
+    /// Castling rights for a king or rook that isn't on its original position
+    CastlingWithoutPieces(char),
+    EnPassant(String),
+    HalfmoveClock(String),
+    FullmoveNumber(String),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::FieldCount(count) => {
+                write!(f, "expected 4 to 6 fields, found {}", count)
+            }
+            Self::RankCount(count) => {
+                write!(f, "expected 8 ranks, found {}", count)
+            }
+            Self::RankLength { rank, length } => write!(
+                f,
+                "rank {} describes {} fields instead of 8",
+                rank, length
+            ),
+            Self::UnknownPiece { rank, piece } => {
+                write!(f, "unknown piece `{}` on rank {}", piece, rank)
+            }
+            Self::EmptyFieldCount { rank, digit } => write!(
+                f,
+                "empty fields must be counted from 1 to 8, found `{}` on \
+                 rank {}",
+                digit, rank
+            ),
+            Self::KingCount { color, count } => {
+                write!(f, "{:?} needs exactly one king, found {}", color, count)
+            }
+            Self::PawnOnBackRank { rank } => {
+                write!(f, "pawn on rank {}", rank)
+            }
+            Self::SideToMove(field) => {
+                write!(f, "side to move must be `w` or `b`, found `{}`", field)
+            }
+            Self::OpponentInCheck => {
+                write!(f, "the side that isn't to move is in check")
+            }
+            Self::Castling(field) => write!(
+                f,
+                "castling rights must be `-` or a combination of `KQkq`, \
+                 found `{}`",
+                field
+            ),
+            Self::CastlingWithoutPieces(right) => write!(
+                f,
+                "castling right `{}` without king and rook on their original \
+                 fields",
+                right
+            ),
+            Self::EnPassant(field) => write!(
+                f,
+                "en passant field `{}` is not `-` or an empty field behind a \
+                 pawn that has just moved two fields",
+                field
+            ),
+            Self::HalfmoveClock(field) => write!(
+                f,
+                "halfmove clock must be a non-negative number, found `{}`",
+                field
+            ),
+            Self::FullmoveNumber(field) => write!(
+                f,
+                "fullmove number must be a positive number, found `{}`",
+                field
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+const CASTLING_LETTERS: [(char, Color, CastlingSide); 4] = [
+    ('K', Color::White, CastlingSide::King),
+    ('Q', Color::White, CastlingSide::Queen),
+    ('k', Color::Black, CastlingSide::King),
+    ('q', Color::Black, CastlingSide::Queen),
+];
+
+impl Position {
+    /// Parses a position in Forsyth-Edwards Notation. The halfmove clock and
+    /// fullmove number may be left out, as is common in EPD files
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if !(4..=6).contains(&fields.len()) {
+            return Err(FenError::FieldCount(fields.len()));
+        }
+
+        let mut board = Board::empty();
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError::RankCount(ranks.len()));
+        }
+        for (y, rank) in ranks.iter().enumerate() {
+            let rank_number = 8 - y as u8;
+            let mut x = 0;
+            for c in rank.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    if !(1..=8).contains(&empty) {
+                        return Err(FenError::EmptyFieldCount {
+                            rank: rank_number,
+                            digit: c,
+                        });
+                    }
+                    x += empty as usize;
+                    continue;
+                }
+                let piece =
+                    Piece::from_char(c).ok_or(FenError::UnknownPiece {
+                        rank: rank_number,
+                        piece: c,
+                    })?;
+                if x < 8 {
+                    if piece.figure == Figure::Pawn && (y == 0 || y == 7) {
+                        return Err(FenError::PawnOnBackRank {
+                            rank: rank_number,
+                        });
+                    }
+                    board.set((x as i8, y as i8), Some(piece));
+                }
+                x += 1;
+            }
+            if x != 8 {
+                return Err(FenError::RankLength {
+                    rank: rank_number,
+                    length: x,
+                });
+            }
+        }
+        for color in [Color::White, Color::Black] {
+            let count = board
+                .cells
+                .iter()
+                .flatten()
+                .filter(|&&cell| cell == Some(Piece::new(color, Figure::King)))
+                .count();
+            if count != 1 {
+                return Err(FenError::KingCount { color, count });
+            }
+        }
+
+        let side_to_move = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            field => return Err(FenError::SideToMove(field.to_string())),
+        };
+        if board.is_in_check(side_to_move.flipped()) {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        if fields[2] != "-" {
+            let mut letters: Vec<char> = fields[2].chars().collect();
+            letters.sort_unstable();
+            letters.dedup();
+            if letters.len() != fields[2].len()
+                || !letters.iter().all(|&c| "KQkq".contains(c))
+            {
+                return Err(FenError::Castling(fields[2].to_string()));
+            }
+            for &(c, color, side) in &CASTLING_LETTERS {
+                if !letters.contains(&c) {
+                    continue;
+                }
+                let row = color.back_row();
+                if board.get((KING_COLUMN, row))
+                    != Some(Piece::new(color, Figure::King))
+                    || board.get((side.rook_column(), row))
+                        != Some(Piece::new(color, Figure::Rook))
+                {
+                    return Err(FenError::CastlingWithoutPieces(c));
+                }
+                board.castling.set(color, side, true);
+            }
+        }
+
+        if fields[3] != "-" {
+            let bad_en_passant = || FenError::EnPassant(fields[3].to_string());
+            let pos = parse_square(fields[3]).ok_or_else(bad_en_passant)?;
+            // The pawn that moved belongs to the side that isn't moving now.
+            // It passed `pos` coming from its original position
+            let moved = side_to_move.flipped();
+            let forwards = moved.forwards();
+            if pos.1 != moved.pawn_row() + forwards
+                || board.get((pos.0, pos.1 + forwards))
+                    != Some(Piece::new(moved, Figure::Pawn))
+                || board.get(pos).is_some()
+                || board.get((pos.0, pos.1 - forwards)).is_some()
+            {
+                return Err(bad_en_passant());
+            }
+            board.en_passant = Some(pos);
+        }
+
+        let mut position = Position::from_board(board, side_to_move);
+        if let Some(&field) = fields.get(4) {
+            position.halfmove_clock = field
+                .parse()
+                .map_err(|_| FenError::HalfmoveClock(field.to_string()))?;
+        }
+        if let Some(&field) = fields.get(5) {
+            position.fullmove_number = match field.parse() {
+                Ok(number) if number > 0 => number,
+                _ => return Err(FenError::FullmoveNumber(field.to_string())),
+            };
+        }
+        Ok(position)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let board = &self.board;
+        let mut ranks = Vec::new();
+        for y in 0..8 {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for x in 0..8 {
+                match board.get((x, y)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            rank.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        rank.push(piece.to_char());
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+            }
+            ranks.push(rank);
+        }
+        let side_to_move = match self.side_to_move {
+            Color::White => "w",
+            Color::Black => "b",
+        };
+        let mut castling: String = CASTLING_LETTERS
+            .iter()
+            .filter(|&&(_, color, side)| board.castling.get(color, side))
+            .map(|&(letter, ..)| letter)
+            .collect();
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        let en_passant = board
+            .en_passant
+            .map_or_else(|| "-".to_string(), square_name);
+        format!(
+            "{} {} {} {} {} {}",
+            ranks.join("/"),
+            side_to_move,
+            castling,
+            en_passant,
+            self.halfmove_clock,
+            self.fullmove_number
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position() {
+        let position = Position::from_fen(START_FEN).unwrap();
+        assert_eq!(position.board, Board::new());
+        assert_eq!(position.side_to_move, Color::White);
+        assert_eq!(Position::new().to_fen(), START_FEN);
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b Kq e3 0 3",
+            "8/8/8/8/8/8/8/K6k b - - 42 77",
+        ] {
+            assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn after_moves() {
+        let mut position = Position::new();
+        position.make_move(Move::new((4, 6), (4, 4)));
+        assert_eq!(
+            position.to_fen(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+    }
+
+    #[test]
+    fn missing_clocks() {
+        let position = Position::from_fen("8/8/8/8/8/8/8/K6k w - -").unwrap();
+        assert_eq!(position.halfmove_clock, 0);
+        assert_eq!(position.fullmove_number, 1);
+    }
+
+    #[test]
+    fn errors() {
+        for (fen, error) in [
+            ("8/8/8/8 w - - 0 1 x", FenError::FieldCount(7)),
+            ("8/8/8/8/8/8/8 w - - 0 1", FenError::RankCount(7)),
+            (
+                "8/8/8/8/8/8/8/K5k w - - 0 1",
+                FenError::RankLength { rank: 1, length: 7 },
+            ),
+            (
+                "8/8/8/8/8/8/8/K6kk w - - 0 1",
+                FenError::RankLength { rank: 1, length: 9 },
+            ),
+            (
+                "8/8/8/8/3x4/8/8/K6k w - - 0 1",
+                FenError::UnknownPiece {
+                    rank: 4,
+                    piece: 'x',
+                },
+            ),
+            (
+                "8/8/8/8/8/8/8/K7 w - - 0 1",
+                FenError::KingCount {
+                    color: Color::Black,
+                    count: 0,
+                },
+            ),
+            (
+                "P7/8/8/8/8/8/8/K6k w - - 0 1",
+                FenError::PawnOnBackRank { rank: 8 },
+            ),
+            (
+                "8/8/8/8/8/8/8/K6k x - - 0 1",
+                FenError::SideToMove("x".to_string()),
+            ),
+            (
+                "8/8/8/8/8/8/8/K6k w KX - 0 1",
+                FenError::Castling("KX".to_string()),
+            ),
+            (
+                "8/8/8/8/8/8/8/K6k w K - 0 1",
+                FenError::CastlingWithoutPieces('K'),
+            ),
+            (
+                "8/8/8/8/8/8/8/K6k w - e6 0 1",
+                FenError::EnPassant("e6".to_string()),
+            ),
+            (
+                "8/8/8/8/8/8/8/K05k w - - 0 1",
+                FenError::EmptyFieldCount {
+                    rank: 1,
+                    digit: '0',
+                },
+            ),
+            ("8/8/8/8/8/8/8/K5rk b - - 0 1", FenError::OpponentInCheck),
+            (
+                "4k3/8/4n3/4p3/8/8/8/4K3 w - e6 0 1",
+                FenError::EnPassant("e6".to_string()),
+            ),
+            (
+                "4k3/4n3/8/4p3/8/8/8/4K3 w - e6 0 1",
+                FenError::EnPassant("e6".to_string()),
+            ),
+            (
+                "8/8/8/8/8/8/8/K6k w - - -1 1",
+                FenError::HalfmoveClock("-1".to_string()),
+            ),
+            (
+                "8/8/8/8/8/8/8/K6k w - - 0 0",
+                FenError::FullmoveNumber("0".to_string()),
+            ),
+        ] {
+            assert_eq!(Position::from_fen(fen).unwrap_err(), error, "{}", fen);
+        }
+    }
+}
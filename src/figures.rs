@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::board::{Board, Pos, Score};
+use crate::board::{Board, Pos};
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Piece {
@@ -25,6 +25,35 @@ impl Color {
             Self::Black => Self::White,
         }
     }
+
+    /// The direction pawns of this color move in along the y axis
+    pub fn forwards(self) -> i8 {
+        -self.as_number()
+    }
+
+    /// The row on which the pieces of this color start
+    pub fn back_row(self) -> i8 {
+        match self {
+            Self::White => 7,
+            Self::Black => 0,
+        }
+    }
+
+    /// The row on which the pawns of this color start
+    pub fn pawn_row(self) -> i8 {
+        match self {
+            Self::White => 6,
+            Self::Black => 1,
+        }
+    }
+
+    /// 0 for white and 1 for black, to index tables by color
+    pub fn index(self) -> usize {
+        match self {
+            Self::White => 0,
+            Self::Black => 1,
+        }
+    }
 }
 
 #[derive(Serialize, Copy, Clone, PartialEq, Eq, Debug, Deserialize)]
@@ -37,7 +66,84 @@ pub enum Figure {
     King,
 }
 
+impl Figure {
+    /// The uppercase letter used for this figure in FEN and algebraic notation
+    pub fn letter(self) -> char {
+        match self {
+            Self::Pawn => 'P',
+            Self::Bishop => 'B',
+            Self::Knight => 'N',
+            Self::Rook => 'R',
+            Self::Queen => 'Q',
+            Self::King => 'K',
+        }
+    }
+
+    /// Parses an uppercase letter as returned by `letter`
+    pub fn from_letter(letter: char) -> Option<Self> {
+        Some(match letter {
+            'P' => Self::Pawn,
+            'B' => Self::Bishop,
+            'N' => Self::Knight,
+            'R' => Self::Rook,
+            'Q' => Self::Queen,
+            'K' => Self::King,
+            _ => return None,
+        })
+    }
+}
+
+pub const KING_STEPS: [(i8, i8); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+];
+
+pub const KNIGHT_JUMPS: [(i8, i8); 8] = [
+    (1, 2),
+    (-1, 2),
+    (1, -2),
+    (-1, -2),
+    (2, 1),
+    (-2, 1),
+    (2, -1),
+    (-2, -1),
+];
+
+pub const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+pub const BISHOP_DIRECTIONS: [(i8, i8); 4] =
+    [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
 impl Piece {
+    pub const fn new(color: Color, figure: Figure) -> Self {
+        Self { color, figure }
+    }
+
+    /// The FEN letter of this piece, uppercase for white and lowercase for
+    /// black
+    pub fn to_char(self) -> char {
+        match self.color {
+            Color::White => self.figure.letter(),
+            Color::Black => self.figure.letter().to_ascii_lowercase(),
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Self> {
+        let color = if c.is_ascii_uppercase() {
+            Color::White
+        } else {
+            Color::Black
+        };
+        Figure::from_letter(c.to_ascii_uppercase())
+            .map(|figure| Self::new(color, figure))
+    }
+
     pub fn reachables(
         self,
         pos: Pos,
@@ -94,19 +200,10 @@ impl Piece {
                 )
             }
             Figure::King => {
-                for (x, y) in [
-                    (1, 0),
-                    (-1, 0),
-                    (0, 1),
-                    (0, -1),
-                    (1, 1),
-                    (1, -1),
-                    (-1, 1),
-                    (-1, -1),
-                ] {
+                for (x, y) in KING_STEPS {
                     let target = (x + pos.0, y + pos.1);
                     if !board.is_inside(target) {
-                        break;
+                        continue;
                     }
                     match board.get(target) {
                         Some(Piece { color, .. }) if color == self.color => {
@@ -120,19 +217,10 @@ impl Piece {
                 }
             }
             Figure::Knight => {
-                for (x, y) in [
-                    (1, 2),
-                    (-1, 2),
-                    (1, -2),
-                    (-1, -2),
-                    (2, 1),
-                    (-2, 1),
-                    (2, -1),
-                    (-2, -1),
-                ] {
+                for (x, y) in KNIGHT_JUMPS {
                     let target = (x + pos.0, y + pos.1);
                     if !board.is_inside(target) {
-                        break;
+                        continue;
                     }
                     match board.get(target) {
                         Some(Piece { color, .. }) if color == self.color => {
@@ -146,110 +234,42 @@ impl Piece {
                 }
             }
             Figure::Pawn => {
-                match self.color {
-                    Color::White => {
-                        // Forwards
-                        let target = (pos.0 + 0, pos.1 - 1);
-                        if board.is_inside(target) {
-                            match board.get(target) {
-                                None => {
-                                    // Empty field
-                                    insert_into(target);
-                                    // Forwards double
-                                    if pos.1 == 6 {
-                                        let target = (pos.0 + 0, pos.1 - 2);
-                                        if board.is_inside(target) {
-                                            match board.get(target) {
-                                                None => {
-                                                    // Empty field
-                                                    insert_into(target);
-                                                }
-                                                _ => {}
-                                            }
-                                        }
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        // Left hit
-                        let target = (pos.0 - 1, pos.1 - 1);
-                        if board.is_inside(target) {
-                            match board.get(target) {
-                                Some(Piece { color, .. })
-                                    if color != self.color =>
-                                {
-                                    // Enemy figure
-                                    insert_into(target);
-                                }
-                                _ => {}
-                            }
-                        }
-                        // Right hit
-                        let target = (pos.0 + 1, pos.1 - 1);
-                        if board.is_inside(target) {
-                            match board.get(target) {
-                                Some(Piece { color, .. })
-                                    if color != self.color =>
-                                {
-                                    //Enemy figure
-                                    insert_into(target);
-                                }
-                                _ => {}
-                            }
-                        }
+                let forwards = self.color.forwards();
+                // Forwards
+                let target = (pos.0, pos.1 + forwards);
+                if board.is_inside(target) && board.get(target).is_none() {
+                    // Empty field
+                    insert_into(target);
+                    // Forwards double
+                    let target = (pos.0, pos.1 + 2 * forwards);
+                    if pos.1 == self.color.pawn_row()
+                        && board.get(target).is_none()
+                    {
+                        insert_into(target);
                     }
-                    Color::Black => {
-                        // Forwards
-                        let target = (pos.0 + 0, pos.1 + 1);
-                        if board.is_inside(target) {
-                            match board.get(target) {
-                                None => {
-                                    // Empty field
-                                    insert_into(target);
-                                    // Forwards double
-                                    if pos.1 == 1 {
-                                        let target = (pos.0 + 0, pos.1 + 2);
-                                        if board.is_inside(target) {
-                                            match board.get(target) {
-                                                None => {
-                                                    // Empty field
-                                                    insert_into(target);
-                                                }
-                                                _ => {}
-                                            }
-                                        }
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        // Left hit
-                        let target = (pos.0 - 1, pos.1 + 1);
-                        if board.is_inside(target) {
-                            match board.get(target) {
-                                Some(Piece { color, .. })
-                                    if color != self.color =>
-                                {
-                                    //Enemy figure
-                                    insert_into(target);
-                                }
-                                _ => {}
-                            }
+                }
+                // Left and right hit
+                for dx in [-1, 1] {
+                    let target = (pos.0 + dx, pos.1 + forwards);
+                    if !board.is_inside(target) {
+                        continue;
+                    }
+                    match board.get(target) {
+                        Some(Piece { color, .. }) if color != self.color => {
+                            // Enemy figure
+                            insert_into(target);
                         }
-                        // Right hit
-                        let target = (pos.0 + 1, pos.1 + 1);
-                        if board.is_inside(target) {
-                            match board.get(target) {
-                                Some(Piece { color, .. })
-                                    if color != self.color =>
-                                {
-                                    // Enemy figure
-                                    insert_into(target);
-                                }
-                                _ => {}
-                            }
+                        None if Some(target) == board.en_passant
+                            && board.get((target.0, pos.1))
+                                == Some(Piece::new(
+                                    self.color.flipped(),
+                                    Figure::Pawn,
+                                )) =>
+                        {
+                            // En passant, hitting the pawn next to this one
+                            insert_into(target);
                         }
+                        _ => {}
                     }
                 }
             }
@@ -262,19 +282,6 @@ impl Piece {
         self.reachables(pos, board, |pos| result.push(pos));
         result
     }
-
-    pub fn score(self) -> Score {
-        use Figure::*;
-        let score = match self.figure {
-            Pawn => 1,
-            Bishop => 3,
-            Knight => 3,
-            Rook => 5,
-            Queen => 9,
-            King => 1000,
-        };
-        score * self.color.as_number() as i32
-    }
 }
 
 #[cfg(test)]
@@ -283,12 +290,13 @@ mod tests {
     #[test]
     fn rook() {
         let mut board = Board::empty();
-        board.0[0][0] = Some(Piece {
+        board.cells[0][0] = Some(Piece {
             color: Color::White,
             figure: Figure::Rook,
         });
-        let reachables =
-            board.0[0][0].unwrap().reachables_collect((0, 0), &board);
+        let reachables = board.cells[0][0]
+            .unwrap()
+            .reachables_collect((0, 0), &board);
         assert_eq!(
             reachables,
             vec![
@@ -313,20 +321,21 @@ mod tests {
     #[test]
     fn blocked_rook() {
         let mut board = Board::empty();
-        board.0[0][0] = Some(Piece {
+        board.cells[0][0] = Some(Piece {
             color: Color::White,
             figure: Figure::Rook,
         });
-        board.0[5][0] = Some(Piece {
+        board.cells[5][0] = Some(Piece {
             color: Color::White,
             figure: Figure::Pawn,
         });
-        board.0[0][3] = Some(Piece {
+        board.cells[0][3] = Some(Piece {
             color: Color::Black,
             figure: Figure::Pawn,
         });
-        let mut reachables =
-            board.0[0][0].unwrap().reachables_collect((0, 0), &board);
+        let mut reachables = board.cells[0][0]
+            .unwrap()
+            .reachables_collect((0, 0), &board);
         reachables.sort();
         let mut expected =
             vec![(1, 0), (2, 0), (3, 0), (4, 0), (0, 1), (0, 2), (0, 3)];
@@ -337,24 +346,25 @@ mod tests {
     #[test]
     fn white_king() {
         let mut board = Board::empty();
-        board.0[4][4] = Some(Piece {
+        board.cells[4][4] = Some(Piece {
             color: Color::White,
             figure: Figure::King,
         });
-        board.0[5][4] = Some(Piece {
+        board.cells[5][4] = Some(Piece {
             color: Color::White,
             figure: Figure::Pawn,
         });
-        board.0[3][3] = Some(Piece {
+        board.cells[3][3] = Some(Piece {
             color: Color::Black,
             figure: Figure::Pawn,
         });
-        board.0[3][5] = Some(Piece {
+        board.cells[3][5] = Some(Piece {
             color: Color::Black,
             figure: Figure::Pawn,
         });
-        let mut reachables =
-            board.0[4][4].unwrap().reachables_collect((4, 4), &board);
+        let mut reachables = board.cells[4][4]
+            .unwrap()
+            .reachables_collect((4, 4), &board);
         reachables.sort();
         let mut expected =
             vec![(3, 3), (4, 3), (5, 3), (5, 5), (4, 5), (3, 5), (3, 4)];
@@ -365,24 +375,25 @@ mod tests {
     #[test]
     fn black_king() {
         let mut board = Board::empty();
-        board.0[4][4] = Some(Piece {
+        board.cells[4][4] = Some(Piece {
             color: Color::Black,
             figure: Figure::King,
         });
-        board.0[5][4] = Some(Piece {
+        board.cells[5][4] = Some(Piece {
             color: Color::Black,
             figure: Figure::Pawn,
         });
-        board.0[3][3] = Some(Piece {
+        board.cells[3][3] = Some(Piece {
             color: Color::White,
             figure: Figure::Pawn,
         });
-        board.0[3][5] = Some(Piece {
+        board.cells[3][5] = Some(Piece {
             color: Color::White,
             figure: Figure::Pawn,
         });
-        let mut reachables =
-            board.0[4][4].unwrap().reachables_collect((4, 4), &board);
+        let mut reachables = board.cells[4][4]
+            .unwrap()
+            .reachables_collect((4, 4), &board);
         reachables.sort();
         let mut expected =
             vec![(3, 3), (4, 3), (5, 3), (5, 5), (4, 5), (3, 5), (3, 4)];
@@ -0,0 +1,217 @@
+//! How long the engine may think about a move
+
+use std::time::{Duration, Instant};
+
+/// The deepest search that is ever started, in plies
+pub const MAX_DEPTH: usize = 64;
+
+/// Moves assumed to be left in the game if the time control doesn't say
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+/// How many times its budget a search may take before it is aborted
+const HARD_LIMIT_FACTOR: u32 = 3;
+
+/// Every depth takes about this many times as long as the previous one
+const BRANCHING_FACTOR: u32 = 4;
+
+/// When a search has to stop. Without any limit, it only stops when told to
+/// or when reaching `MAX_DEPTH`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchLimits {
+    /// Maximum depth in plies
+    pub depth: Option<usize>,
+    /// Fixed time for this move
+    pub movetime: Option<Duration>,
+    /// The time left on the clock of the side to move
+    pub clock: Option<Duration>,
+    /// The time added to the clock after each move
+    pub increment: Duration,
+    /// Moves until the next time control, if there is one
+    pub moves_to_go: Option<u32>,
+    pub nodes: Option<u64>,
+    /// Search until told to stop, ignoring all other limits
+    pub infinite: bool,
+}
+
+impl SearchLimits {
+    /// Limits to searching `depth` plies deep
+    pub fn depth(depth: usize) -> Self {
+        Self {
+            depth: Some(depth),
+            ..Self::default()
+        }
+    }
+
+    /// Whether the search would only stop when told to
+    pub fn is_unlimited(&self) -> bool {
+        self.infinite
+            || self.depth.is_none()
+                && self.movetime.is_none()
+                && self.clock.is_none()
+                && self.nodes.is_none()
+    }
+
+    pub fn max_depth(&self) -> usize {
+        match self.depth {
+            Some(depth) if !self.infinite => depth.clamp(1, MAX_DEPTH),
+            _ => MAX_DEPTH,
+        }
+    }
+}
+
+/// Decides whether there is time for another iteration of the search and
+/// when a running one has to be aborted
+#[derive(Clone, Debug)]
+pub struct TimeManager {
+    start: Instant,
+    /// The time the search should take
+    budget: Option<Duration>,
+    /// The time after which the search is aborted, even in the middle of an
+    /// iteration
+    hard_limit: Option<Duration>,
+    max_nodes: Option<u64>,
+}
+
+impl TimeManager {
+    /// Starts the clock for a search with `limits`
+    pub fn new(limits: &SearchLimits) -> Self {
+        if limits.infinite {
+            return Self {
+                start: Instant::now(),
+                budget: None,
+                hard_limit: None,
+                max_nodes: None,
+            };
+        }
+        let budget = time_budget(limits);
+        let hard_limit = match (limits.movetime, limits.clock) {
+            (Some(movetime), _) => Some(movetime),
+            // Overrunning the budget a bit is fine, but not losing on time
+            (None, Some(clock)) => {
+                budget.map(|budget| (budget * HARD_LIMIT_FACTOR).min(clock / 2))
+            }
+            (None, None) => None,
+        };
+        Self {
+            start: Instant::now(),
+            budget,
+            hard_limit,
+            max_nodes: limits.nodes,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Whether to search one ply deeper after `nodes` nodes were searched
+    /// and the last iteration took `last_iteration`. An iteration that likely
+    /// can't be completed in time isn't started
+    pub fn should_continue(
+        &self,
+        last_iteration: Duration,
+        nodes: u64,
+    ) -> bool {
+        let out_of_time = self.budget.is_some_and(|budget| {
+            self.elapsed() + last_iteration * BRANCHING_FACTOR > budget
+        });
+        let out_of_nodes = self.max_nodes.is_some_and(|max| nodes >= max);
+        !out_of_time && !out_of_nodes
+    }
+
+    /// Whether a running iteration has to be aborted because it took too
+    /// long. Looks at the clock, so it shouldn't be asked for every node
+    pub fn out_of_time(&self) -> bool {
+        self.hard_limit
+            .is_some_and(|hard_limit| self.elapsed() > hard_limit)
+    }
+
+    /// Whether a running iteration has to be aborted after `nodes` nodes
+    pub fn out_of_nodes(&self, nodes: u64) -> bool {
+        self.max_nodes.is_some_and(|max| nodes >= max)
+    }
+}
+
+/// How long to think about a move, if there is any limit on time
+fn time_budget(limits: &SearchLimits) -> Option<Duration> {
+    if let Some(movetime) = limits.movetime {
+        return Some(movetime);
+    }
+    let clock = limits.clock?;
+    let moves_to_go = limits.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+    // Never use more than half of the clock, the increment alone doesn't
+    // help when the clock is nearly empty
+    Some((clock / moves_to_go + limits.increment * 3 / 4).min(clock / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budgets() {
+        let limits = SearchLimits {
+            clock: Some(Duration::from_secs(60)),
+            increment: Duration::from_secs(2),
+            ..SearchLimits::default()
+        };
+        assert_eq!(time_budget(&limits), Some(Duration::from_millis(3500)));
+        let limits = SearchLimits {
+            clock: Some(Duration::from_secs(1)),
+            increment: Duration::from_secs(2),
+            moves_to_go: Some(1),
+            ..limits
+        };
+        assert_eq!(time_budget(&limits), Some(Duration::from_millis(500)));
+        let limits = SearchLimits {
+            movetime: Some(Duration::from_secs(5)),
+            ..limits
+        };
+        assert_eq!(time_budget(&limits), Some(Duration::from_secs(5)));
+        assert_eq!(time_budget(&SearchLimits::depth(3)), None);
+    }
+
+    #[test]
+    fn stopping() {
+        let limits = SearchLimits {
+            movetime: Some(Duration::from_secs(1)),
+            nodes: Some(1000),
+            ..SearchLimits::default()
+        };
+        let time_manager = TimeManager::new(&limits);
+        assert!(time_manager.should_continue(Duration::from_millis(10), 100));
+        assert!(!time_manager.should_continue(Duration::from_millis(500), 100));
+        assert!(!time_manager.should_continue(Duration::from_millis(10), 1000));
+        let infinite = SearchLimits {
+            infinite: true,
+            ..limits
+        };
+        let time_manager = TimeManager::new(&infinite);
+        assert!(time_manager.should_continue(Duration::from_secs(10), 1000));
+        assert_eq!(infinite.max_depth(), MAX_DEPTH);
+        assert!(infinite.is_unlimited());
+        assert!(!limits.is_unlimited());
+        assert_eq!(SearchLimits::depth(100).max_depth(), MAX_DEPTH);
+    }
+
+    #[test]
+    fn aborting() {
+        let limits = SearchLimits {
+            clock: Some(Duration::from_secs(60)),
+            nodes: Some(1000),
+            ..SearchLimits::default()
+        };
+        let time_manager = TimeManager::new(&limits);
+        assert_eq!(time_manager.hard_limit, Some(Duration::from_secs(6)));
+        assert!(!time_manager.out_of_nodes(999));
+        assert!(time_manager.out_of_nodes(1000));
+        assert!(!time_manager.out_of_time());
+        let limits = SearchLimits {
+            movetime: Some(Duration::ZERO),
+            ..SearchLimits::default()
+        };
+        assert!(TimeManager::new(&limits).out_of_time());
+        let unlimited = TimeManager::new(&SearchLimits::default());
+        assert!(!unlimited.out_of_time() && !unlimited.out_of_nodes(0));
+    }
+}
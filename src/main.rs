@@ -1,53 +1,314 @@
-#![feature(test)]
+#![cfg_attr(feature = "bench", feature(test))]
+#[cfg(feature = "bench")]
 extern crate test;
 
 mod ai;
 mod board;
+mod eval;
+mod fen;
 mod figures;
+mod limits;
+mod notation;
+mod ordering;
+mod pawns;
+mod pgn;
+mod position;
+mod score;
+mod see;
+mod tt;
+mod uci;
+mod xboard;
+mod zobrist;
 
 //use std::time::Instant;
 
+use std::{
+    io::{BufRead, Write},
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use serde::{Deserialize, Serialize};
+
+use ai::SearchHandle;
 use board::{Board, Move};
 use figures::Color;
+use limits::SearchLimits;
+use pawns::PawnTable;
+use pgn::PgnGame;
+use position::{GameStatus, Position};
+use score::Eval;
+use tt::{TranspositionTable, DEFAULT_SIZE_MB};
+
+/// A line of input: Either only the board, with the color to move given on
+/// the command line, a position in FEN followed by moves in standard or long
+/// algebraic notation or a whole game
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Request {
+    /// Not a game, but something for the engine to do, e.g. `stop` thinking,
+    /// `moves` to list the legal moves or `eval` to explain the evaluation.
+    /// These look at the position in `fen` after `moves`, or else at the last
+    /// one asked for. Tried first, as the other variants ignore `cmd`
+    Command {
+        cmd: String,
+        fen: Option<String>,
+        #[serde(default)]
+        moves: Vec<String>,
+    },
+    /// Only the pieces, so neither en passant nor lost castling rights nor
+    /// repetitions can be known, see `Board::from`
+    Board(Board),
+    Fen {
+        fen: String,
+        #[serde(default)]
+        moves: Vec<String>,
+    },
+    Position(Position),
+    /// The first game of a PGN text, the answer contains the game continued
+    /// by the engine's move
+    Pgn {
+        pgn: String,
+    },
+}
+
+/// The answer to a `moves` command, so that user interfaces don't have to
+/// know the rules themselves
+#[derive(Serialize)]
+struct LegalMoves {
+    moves: Vec<Move>,
+}
+
+/// The answer to a request: The chosen move, if there is any, and the status
+/// of the game after it
+#[derive(Serialize)]
+struct Response {
+    #[serde(rename = "move")]
+    mov: Option<Move>,
+    /// The move in standard algebraic notation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    san: Option<String>,
+    /// The move in long algebraic notation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uci: Option<String>,
+    status: GameStatus,
+    /// The score of the move in the view of the engine, like `35` in
+    /// centipawns, `M3` for mating in 3 moves or `-M2` for being mated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pgn: Option<String>,
+}
 
 fn main() {
     let args: Vec<_> = std::env::args().skip(1).collect();
-    assert_eq!(
-        args.len(),
-        2,
-        "Expected 2 arguments: color and max_look_ahead"
-    );
-    let color = match args[0].to_lowercase().as_str() {
+    match args.as_slice() {
+        [] => {
+            // Chess GUIs start engines without arguments and then say which
+            // protocol they speak
+            let mut lines = std::io::stdin().lock().lines().map(Result::unwrap);
+            let first_line = lines.next().unwrap_or_default();
+            let lines = std::iter::once(first_line.clone()).chain(lines);
+            match first_line.trim() {
+                "uci" => uci::run(lines, std::io::stdout()),
+                "xboard" => xboard::run(lines, std::io::stdout()),
+                "" => {}
+                other => println!("Unknown protocol: {}", other),
+            }
+        }
+        [protocol] if protocol == "uci" => uci::run(
+            std::io::stdin().lock().lines().map(Result::unwrap),
+            std::io::stdout(),
+        ),
+        [protocol] if protocol == "xboard" => xboard::run(
+            std::io::stdin().lock().lines().map(Result::unwrap),
+            std::io::stdout(),
+        ),
+        [color, max_look_ahead] => run_json(color, max_look_ahead),
+        _ => panic!(
+            "Expected no arguments, `uci`, `xboard` or 2 arguments: color and \
+             max_look_ahead"
+        ),
+    }
+}
+
+/// Reads requests as JSON lines from stdin and answers each with a JSON line.
+/// The engine thinks in the background, so that it can be stopped by
+/// `{"cmd": "stop"}` and answers with the best move found so far
+fn run_json(color: &str, max_look_ahead: &str) {
+    let color = match color.to_lowercase().as_str() {
         "white" => Color::White,
         "black" => Color::Black,
         _ => panic!("First command line argument must be `black` or `white`"),
     };
-    let max_look_ahead = args[1]
+    let max_look_ahead = max_look_ahead
         .parse()
         .expect("Second command line argument must be a positve integer");
-    loop {
-        let mut s = String::new();
-        std::io::stdin().read_line(&mut s).unwrap();
-        match serde_json::from_str::<Board>(&s) {
-            Ok(board) => {
-                //let time_taken = std::time::Instant::now();
-                let choice = make_move(&board, color, max_look_ahead);
-                //dbg!(time_taken.elapsed());
-                serde_json::to_writer(std::io::stdout(), &choice).unwrap();
-                println!();
+    let tt = Arc::new(TranspositionTable::new(DEFAULT_SIZE_MB));
+    let mut search: Option<SearchHandle<()>> = None;
+    let mut last_position = Position::new();
+    for line in std::io::stdin().lock().lines() {
+        let line = line.unwrap();
+        let request = serde_json::from_str::<Request>(&line);
+        if let Ok(Request::Command { cmd, fen, moves }) = &request {
+            let position = || match fen {
+                Some(fen) => parse_game(fen, moves)
+                    .map_err(|err| format!("Bad game: {}", err)),
+                None => Ok(last_position.clone()),
+            };
+            match cmd.as_str() {
+                "stop" => {
+                    if let Some(search) = &search {
+                        search.stop();
+                    }
+                }
+                // What was learned about earlier positions doesn't help in
+                // an unrelated game
+                "newgame" => tt.clear(),
+                "moves" | "eval" => {
+                    if let Some(search) = search.take() {
+                        search.join();
+                    }
+                    match position() {
+                        Ok(position) if cmd == "moves" => {
+                            let moves = position.legal_moves();
+                            print_json(&LegalMoves { moves });
+                        }
+                        Ok(position) => print_json(&eval::evaluate_trace(
+                            &position.board,
+                            &mut PawnTable::new(),
+                        )),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+                _ => println!("Unknown command: {}", cmd),
             }
+            continue;
+        }
+        // Answer in the order of the requests
+        if let Some(search) = search.take() {
+            search.join();
+        }
+        let (position, game) = match request.map_err(|err| err.to_string()) {
+            Ok(request) => match start_game(request, color) {
+                Ok(game) => game,
+                Err(err) => {
+                    println!("{}", err);
+                    continue;
+                }
+            },
             Err(err) => {
                 println!("Bad parsing of board: {}", err);
+                continue;
             }
+        };
+        last_position = position.clone();
+        let tt = Arc::clone(&tt);
+        search = Some(SearchHandle::spawn(move |stop| {
+            answer(position, game, max_look_ahead, &tt, stop)
+        }));
+    }
+    if let Some(search) = search {
+        search.join();
+    }
+}
+
+/// The position to move in and the game to continue, if there is one
+fn start_game(
+    request: Request,
+    color: Color,
+) -> Result<(Position, Option<PgnGame>), String> {
+    match request {
+        Request::Board(board) => Ok((Position::from_board(board, color), None)),
+        Request::Fen { fen, moves } => parse_game(&fen, &moves)
+            .map(|position| (position, None))
+            .map_err(|err| format!("Bad game: {}", err)),
+        Request::Position(position) => Ok((position, None)),
+        Request::Pgn { pgn } => match pgn::parse_pgn(&pgn) {
+            Ok(games) if !games.is_empty() => {
+                let game = games.into_iter().next().unwrap();
+                Ok((game.position(), Some(game)))
+            }
+            Ok(_) => Err("Bad PGN: no game found".to_string()),
+            Err(err) => Err(format!("Bad PGN: {}", err)),
+        },
+        Request::Command { .. } => unreachable!("Commands aren't games"),
+    }
+}
+
+/// Prints `value` as a line of JSON
+fn print_json(value: &impl Serialize) {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, value).unwrap();
+    writeln!(stdout).unwrap();
+    stdout.flush().unwrap();
+}
+
+/// Searches a move in `position` and prints the response
+fn answer(
+    mut position: Position,
+    mut game: Option<PgnGame>,
+    max_look_ahead: usize,
+    tt: &TranspositionTable,
+    stop: &AtomicBool,
+) {
+    //let time_taken = std::time::Instant::now();
+    let choice = make_move(&position, max_look_ahead, tt, stop);
+    //dbg!(time_taken.elapsed());
+    let mov = choice.as_ref().map(|(result, _)| result.best_move);
+    let san = mov.map(|mov| position.to_san(mov));
+    let score = choice
+        .as_ref()
+        .map(|(result, _)| Eval::from(result.score).to_string());
+    if let Some(mov) = mov {
+        position.make_move(mov);
+    }
+    let status = position.status();
+    if let Some(game) = &mut game {
+        if let Some((result, depth)) = choice {
+            game.push(result.best_move).add_eval(result.score, depth);
         }
+        game.result = status.pgn_result().to_string();
+    }
+    let response = Response {
+        mov,
+        san,
+        uci: mov.map(Move::to_uci),
+        status,
+        score,
+        pgn: game.map(|game| game.to_pgn()),
+    };
+    print_json(&response);
+}
+
+fn parse_game(
+    fen: &str,
+    moves: &[String],
+) -> Result<Position, Box<dyn std::error::Error>> {
+    let mut position = Position::from_fen(fen)?;
+    for text in moves {
+        let mov = position.parse_move(text)?;
+        position.make_move(mov);
     }
+    Ok(position)
 }
 
+/// The result of the deepest completed search together with its depth
 fn make_move(
-    board: &Board,
-    color: Color,
+    position: &Position,
     max_look_ahead: usize,
-) -> Option<Move> {
-    //dbg!(ai::total_score(board));
-    ai::calculate_best_move(board, color, max_look_ahead)
+    tt: &TranspositionTable,
+    stop: &AtomicBool,
+) -> Option<(ai::SearchResult, usize)> {
+    //dbg!(eval::evaluate(&position.board));
+    let limits = SearchLimits::depth(max_look_ahead + 1);
+    let mut depth = 0;
+    let result = ai::iterative_deepening(
+        position,
+        &limits,
+        &ai::SearchOptions::default(),
+        tt,
+        stop,
+        |iteration| depth = iteration.depth,
+    );
+    result.map(|result| (result, depth))
 }
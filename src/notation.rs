@@ -0,0 +1,331 @@
+//! Standard algebraic notation (SAN) like "Nf3" and long algebraic notation as
+//! used by UCI like "g1f3"
+
+use std::fmt;
+
+use crate::{board::*, figures::*, position::Position};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NotationError {
+    /// The text isn't a move in the expected notation at all
+    Syntax(String),
+    /// No legal move matches the text
+    Illegal(String),
+    /// Several legal moves match the text
+    Ambiguous(String),
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Syntax(text) => write!(f, "`{}` is not a valid move", text),
+            Self::Illegal(text) => write!(f, "`{}` is not a legal move", text),
+            Self::Ambiguous(text) => {
+                write!(f, "`{}` matches more than one legal move", text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+impl Move {
+    /// The move in long algebraic notation, e.g. "e2e4" or "e7e8q"
+    pub fn to_uci(self) -> String {
+        let mut result = square_name(self.from) + &square_name(self.to);
+        if let Some(figure) = self.promotion {
+            result.push(figure.letter().to_ascii_lowercase());
+        }
+        result
+    }
+}
+
+impl Position {
+    /// Parses a move in long algebraic notation, e.g. "e2e4" or "e7e8q"
+    pub fn parse_uci(&self, text: &str) -> Result<Move, NotationError> {
+        let syntax_error = || NotationError::Syntax(text.to_string());
+        if !(4..=5).contains(&text.len()) || !text.is_ascii() {
+            return Err(syntax_error());
+        }
+        let from = parse_square(&text[0..2]).ok_or_else(syntax_error)?;
+        let to = parse_square(&text[2..4]).ok_or_else(syntax_error)?;
+        let promotion = match text[4..].chars().next() {
+            Some(c) => Some(
+                Figure::from_letter(c.to_ascii_uppercase())
+                    .filter(|figure| PROMOTIONS.contains(figure))
+                    .ok_or_else(syntax_error)?,
+            ),
+            None => None,
+        };
+        let mov = Move {
+            from,
+            to,
+            promotion,
+        };
+        if self.legal_moves().contains(&mov) {
+            Ok(mov)
+        } else {
+            Err(NotationError::Illegal(text.to_string()))
+        }
+    }
+
+    /// The legal move `mov` in standard algebraic notation, e.g. "Nf3",
+    /// "exd5", "O-O" or "e8=N+"
+    pub fn to_san(&self, mov: Move) -> String {
+        let board = &self.board;
+        let piece = board.get(mov.from).expect("Bad move");
+        let mut san = String::new();
+        if piece.figure == Figure::King && (mov.to.0 - mov.from.0).abs() == 2 {
+            san.push_str(if mov.to.0 > mov.from.0 {
+                "O-O"
+            } else {
+                "O-O-O"
+            });
+        } else {
+            let captures =
+                board.get(mov.to).is_some() || board.is_en_passant(mov);
+            if piece.figure == Figure::Pawn {
+                if captures {
+                    san.push(square_name(mov.from).remove(0));
+                }
+            } else {
+                san.push(piece.figure.letter());
+                // Name the column, row or both of the moving piece if other
+                // pieces of the same kind could move to the same position
+                let others: Vec<Pos> = self
+                    .legal_moves()
+                    .into_iter()
+                    .filter(|other| {
+                        other.to == mov.to
+                            && other.from != mov.from
+                            && board.get(other.from) == Some(piece)
+                    })
+                    .map(|other| other.from)
+                    .collect();
+                let from = square_name(mov.from);
+                if !others.is_empty() {
+                    if others.iter().all(|other| other.0 != mov.from.0) {
+                        san.push_str(&from[..1]);
+                    } else if others.iter().all(|other| other.1 != mov.from.1) {
+                        san.push_str(&from[1..]);
+                    } else {
+                        san.push_str(&from);
+                    }
+                }
+            }
+            if captures {
+                san.push('x');
+            }
+            san.push_str(&square_name(mov.to));
+            if let Some(figure) = mov.promotion {
+                san.push('=');
+                san.push(figure.letter());
+            }
+        }
+        let mut after = self.clone();
+        after.make_move(mov);
+        if after.board.is_in_check(after.side_to_move) {
+            san.push(if after.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+        san
+    }
+
+    /// Parses a move in standard algebraic notation. Check and annotation
+    /// suffixes like "+", "#" or "!?" are ignored, as are superfluous
+    /// disambiguations
+    pub fn parse_san(&self, text: &str) -> Result<Move, NotationError> {
+        let syntax_error = || NotationError::Syntax(text.to_string());
+        let san = text
+            .trim()
+            .trim_end_matches(|c| "+#!?".contains(c))
+            .trim_end_matches("e.p.")
+            .trim_end();
+        let moves = self.legal_moves();
+        let matches: Vec<Move> = if let Some(side) = castling_side(san) {
+            let king = (KING_COLUMN, self.side_to_move.back_row());
+            let target = (side.king_target_column(), king.1);
+            moves
+                .into_iter()
+                .filter(|mov| {
+                    mov.from == king
+                        && mov.to == target
+                        && self.board.get(king).map(|piece| piece.figure)
+                            == Some(Figure::King)
+                })
+                .collect()
+        } else {
+            if !san.is_ascii() {
+                return Err(syntax_error());
+            }
+            let mut chars = san;
+            let figure = match chars.chars().next() {
+                Some(c) if c.is_ascii_uppercase() => {
+                    chars = &chars[1..];
+                    Figure::from_letter(c).ok_or_else(syntax_error)?
+                }
+                _ => Figure::Pawn,
+            };
+            // The promotion comes last, with or without a `=`
+            let mut promotion = None;
+            if let Some(c) = chars.chars().last() {
+                if let Some(promoted) =
+                    Figure::from_letter(c.to_ascii_uppercase())
+                        .filter(|figure| PROMOTIONS.contains(figure))
+                {
+                    if chars.len() > 2 && !c.is_ascii_digit() {
+                        promotion = Some(promoted);
+                        chars = chars[..chars.len() - 1].trim_end_matches('=');
+                    }
+                }
+            }
+            if chars.len() < 2 {
+                return Err(syntax_error());
+            }
+            let to = parse_square(&chars[chars.len() - 2..])
+                .ok_or_else(syntax_error)?;
+            // Whatever is left names the column and/or row of the piece
+            let mut from_column = None;
+            let mut from_row = None;
+            for c in chars[..chars.len() - 2].chars() {
+                match c {
+                    'a'..='h' => from_column = Some(c as i8 - 'a' as i8),
+                    '1'..='8' => from_row = Some(8 - (c as i8 - '0' as i8)),
+                    'x' | ':' | '-' => {}
+                    _ => return Err(syntax_error()),
+                }
+            }
+            let promotes = figure == Figure::Pawn
+                && to.1 == self.side_to_move.flipped().back_row();
+            if promotes && promotion.is_none() {
+                // Sloppy notation, assume the most common choice
+                promotion = Some(Figure::Queen);
+            }
+            moves
+                .into_iter()
+                .filter(|mov| {
+                    mov.to == to
+                        && mov.promotion == promotion
+                        && self.board.get(mov.from).map(|piece| piece.figure)
+                            == Some(figure)
+                        && from_column.iter().all(|&x| mov.from.0 == x)
+                        && from_row.iter().all(|&y| mov.from.1 == y)
+                })
+                .collect()
+        };
+        match matches.as_slice() {
+            [mov] => Ok(*mov),
+            [] => Err(NotationError::Illegal(text.to_string())),
+            _ => Err(NotationError::Ambiguous(text.to_string())),
+        }
+    }
+
+    /// Parses a move in either standard or long algebraic notation
+    pub fn parse_move(&self, text: &str) -> Result<Move, NotationError> {
+        self.parse_uci(text).or_else(|uci_error| match uci_error {
+            NotationError::Syntax(_) => self.parse_san(text),
+            error => Err(error),
+        })
+    }
+}
+
+fn castling_side(san: &str) -> Option<CastlingSide> {
+    match san {
+        "O-O" | "0-0" => Some(CastlingSide::King),
+        "O-O-O" | "0-0-0" => Some(CastlingSide::Queen),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TWO_KNIGHTS: &str = "4k3/8/8/8/8/2N3N1/8/4K3 w - - 0 1";
+
+    const KIWIPETE: &str =
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    fn round_trip(fen: &str, uci: &str, san: &str) {
+        let position = Position::from_fen(fen).unwrap();
+        let mov = position.parse_uci(uci).unwrap();
+        assert_eq!(mov.to_uci(), uci);
+        assert_eq!(position.to_san(mov), san);
+        assert_eq!(position.parse_san(san), Ok(mov));
+        assert_eq!(position.parse_move(san), Ok(mov));
+        assert_eq!(position.parse_move(uci), Ok(mov));
+    }
+
+    #[test]
+    fn formatting_and_parsing() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        round_trip(start, "g1f3", "Nf3");
+        round_trip(start, "e2e4", "e4");
+        round_trip(KIWIPETE, "e1g1", "O-O");
+        round_trip(KIWIPETE, "e1c1", "O-O-O");
+        round_trip(KIWIPETE, "d5e6", "dxe6");
+        round_trip(KIWIPETE, "e5f7", "Nxf7");
+        round_trip(KIWIPETE, "f3f6", "Qxf6");
+        round_trip(KIWIPETE, "e2a6", "Bxa6");
+        // Both knights can reach e4
+        round_trip(TWO_KNIGHTS, "c3e4", "Nce4");
+        round_trip(TWO_KNIGHTS, "c3b5", "Nb5");
+        round_trip("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8n", "b8=N");
+        round_trip("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q", "b8=Q+");
+        round_trip(
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3",
+            "d4e3",
+            "dxe3",
+        );
+        // Rooks on the same column need the row
+        round_trip("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3", "R1a3");
+        // Queens needing both column and row
+        round_trip("7k/8/8/8/Q1Q5/8/Q7/K7 w - - 0 1", "a4b3", "Qa4b3");
+        round_trip("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", "a1a8", "Ra8#");
+    }
+
+    #[test]
+    fn sloppy_san() {
+        let position = Position::from_fen(KIWIPETE).unwrap();
+        let castle = position.parse_uci("e1g1").unwrap();
+        assert_eq!(position.parse_san("0-0"), Ok(castle));
+        let position = Position::from_fen(TWO_KNIGHTS).unwrap();
+        let knight = position.parse_uci("c3e4").unwrap();
+        assert_eq!(position.parse_san("Nc3e4"), Ok(knight));
+        assert_eq!(position.parse_san("Nc3-e4!?"), Ok(knight));
+        assert_eq!(position.parse_san("Nce4+"), Ok(knight));
+        let promotion =
+            Position::from_fen("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(promotion.parse_san("b8"), promotion.parse_uci("b7b8q"));
+        assert_eq!(promotion.parse_san("b8N"), promotion.parse_uci("b7b8n"));
+    }
+
+    #[test]
+    fn errors() {
+        let position = Position::from_fen(TWO_KNIGHTS).unwrap();
+        assert_eq!(
+            position.parse_san("Ne4"),
+            Err(NotationError::Ambiguous("Ne4".to_string()))
+        );
+        let position = Position::from_fen(KIWIPETE).unwrap();
+        assert_eq!(
+            position.parse_san("Nb6"),
+            Err(NotationError::Illegal("Nb6".to_string()))
+        );
+        assert_eq!(
+            position.parse_san("Zz9"),
+            Err(NotationError::Syntax("Zz9".to_string()))
+        );
+        assert_eq!(
+            position.parse_uci("e2e5"),
+            Err(NotationError::Illegal("e2e5".to_string()))
+        );
+        assert_eq!(
+            position.parse_uci("e2e4x"),
+            Err(NotationError::Syntax("e2e4x".to_string()))
+        );
+    }
+}
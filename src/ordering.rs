@@ -0,0 +1,342 @@
+//! The order in which the search tries moves. Alpha-beta cuts off the most
+//! when the best move comes first, so moves likely to be good are tried
+//! before the others
+
+use crate::{
+    ai::{self, MoveKind},
+    board::*,
+    figures::*,
+    see::{cannot_lose, see},
+};
+
+/// Killer moves kept per ply
+const KILLERS: usize = 2;
+
+/// What the search learned about good quiet moves. Each search thread has its
+/// own, so no synchronization is needed
+pub struct Heuristics {
+    /// Quiet moves that caused a cutoff, indexed by ply, most recent first
+    killers: Vec<[Option<Move>; KILLERS]>,
+    /// How much cutoffs quiet moves caused, indexed by color, from and to
+    history: Vec<[[Score; 64]; 64]>,
+}
+
+impl Heuristics {
+    pub fn new() -> Self {
+        Self {
+            killers: Vec::new(),
+            history: vec![[[0; 64]; 64]; 2],
+        }
+    }
+
+    fn killers(&self, ply: usize) -> [Option<Move>; KILLERS] {
+        self.killers.get(ply).copied().unwrap_or_default()
+    }
+
+    /// Remembers that `mov` caused a beta cutoff `ply` plies from the root
+    /// with `look_ahead` plies left to search
+    pub fn record_cutoff(
+        &mut self,
+        board: &Board,
+        color: Color,
+        mov: Move,
+        ply: usize,
+        look_ahead: usize,
+    ) {
+        // Captures are ordered well enough without
+        if is_tactical(board, mov) {
+            return;
+        }
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None; KILLERS]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(mov) {
+            killers.rotate_right(1);
+            killers[0] = Some(mov);
+        }
+        // Cutoffs far from the leaves save the most work
+        let entry =
+            &mut self.history[color.index()][square(mov.from)][square(mov.to)];
+        *entry = entry.saturating_add((look_ahead * look_ahead) as Score);
+    }
+
+    fn history(&self, color: Color, mov: Move) -> Score {
+        self.history[color.index()][square(mov.from)][square(mov.to)]
+    }
+}
+
+impl Default for Heuristics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn square(pos: Pos) -> usize {
+    pos.0 as usize * 8 + pos.1 as usize
+}
+
+/// Whether `mov` changes the material: captures and promotions
+pub fn is_tactical(board: &Board, mov: Move) -> bool {
+    board.get(mov.to).is_some()
+        || mov.promotion.is_some()
+        || board.is_en_passant(mov)
+}
+
+/// The figures ordered by value, for ranking captures
+fn rank(figure: Figure) -> Score {
+    match figure {
+        Figure::Pawn => 0,
+        Figure::Knight | Figure::Bishop => 1,
+        Figure::Rook => 2,
+        Figure::Queen => 3,
+        Figure::King => 4,
+    }
+}
+
+/// Most valuable victim, least valuable attacker: Capturing a queen with a
+/// pawn comes first, capturing a pawn with the king last
+fn mvv_lva(board: &Board, mov: Move) -> Score {
+    let victim = board.get(mov.to).map_or(0, |piece| rank(piece.figure));
+    let attacker = board.get(mov.from).map_or(0, |piece| rank(piece.figure));
+    let promotion = mov.promotion.map_or(0, rank);
+    (victim + promotion) * 8 - attacker
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stage {
+    BestKnown,
+    GenerateTactical,
+    Tactical,
+    Killers,
+    Quiet,
+    Losing,
+    Done,
+}
+
+/// Generates the legal moves of a position in stages and hands them out one
+/// after another: First the best move known from the transposition table,
+/// then captures and promotions by MVV-LVA, then killer moves, then the other
+/// quiet moves by their history and finally the captures losing material by
+/// static exchange evaluation. Each stage is only generated and sorted when
+/// it is reached, so a cutoff by an early move saves the work
+pub struct MovePicker {
+    stage: Stage,
+    /// Set to `None` if it isn't a legal move of the position
+    best_known: Option<Move>,
+    /// Whether the quiet moves are wanted at all
+    quiet: bool,
+    /// Quiet moves not handed out or sorted into `scored` yet
+    moves: Vec<Move>,
+    /// The moves of the current stage with their scores
+    scored: Vec<(Score, Move)>,
+    /// Captures and promotions losing material, with what they lose
+    losing: Vec<(Score, Move)>,
+    killers: [Option<Move>; KILLERS],
+    /// The side making the moves
+    color: Color,
+}
+
+impl MovePicker {
+    /// Picks the moves of `color` in a position `ply` plies from the root.
+    /// Without `quiet`, only captures and promotions are handed out
+    pub fn new(
+        best_known: Option<Move>,
+        quiet: bool,
+        color: Color,
+        ply: usize,
+        heuristics: &Heuristics,
+    ) -> Self {
+        Self {
+            stage: Stage::BestKnown,
+            best_known,
+            quiet,
+            moves: Vec::new(),
+            scored: Vec::new(),
+            losing: Vec::new(),
+            killers: heuristics.killers(ply),
+            color,
+        }
+    }
+
+    /// The moves of `kind` except for the best known one, which was already
+    /// handed out
+    fn generate(&self, board: &Board, kind: MoveKind) -> Vec<Move> {
+        let mut moves = ai::legal_moves_of_kind(board, self.color, kind);
+        moves.retain(|&mov| Some(mov) != self.best_known);
+        moves
+    }
+
+    /// The next move to try, `None` once all were handed out
+    pub fn next(
+        &mut self,
+        board: &Board,
+        heuristics: &Heuristics,
+    ) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::BestKnown => {
+                    self.stage = Stage::GenerateTactical;
+                    // The table might hold a move of another position with
+                    // the same slot, so it has to be checked
+                    let color = self.color;
+                    let quiet = self.quiet;
+                    self.best_known = self.best_known.filter(|&mov| {
+                        (quiet || is_tactical(board, mov))
+                            && ai::is_legal(board, color, mov)
+                    });
+                    if self.best_known.is_some() {
+                        return self.best_known;
+                    }
+                }
+                Stage::GenerateTactical => {
+                    self.stage = Stage::Tactical;
+                    let mut winning = Vec::new();
+                    for mov in self.generate(board, MoveKind::Tactical) {
+                        // Only look at the exchange if the captured piece
+                        // isn't worth the capturing one anyway
+                        if cannot_lose(board, mov) {
+                            winning.push(mov);
+                        } else {
+                            match see(board, mov) {
+                                exchange if exchange >= 0 => winning.push(mov),
+                                exchange => self.losing.push((exchange, mov)),
+                            }
+                        }
+                    }
+                    self.scored = score_all(winning, |mov| mvv_lva(board, mov));
+                }
+                Stage::Tactical => match pop_best(&mut self.scored) {
+                    Some(mov) => return Some(mov),
+                    None if self.quiet => {
+                        self.stage = Stage::Killers;
+                        self.moves = self.generate(board, MoveKind::Quiet);
+                    }
+                    None => self.stage = Stage::Losing,
+                },
+                Stage::Killers => {
+                    // Killers were quiet where they caused a cutoff, so only
+                    // the quiet moves are searched for them
+                    for index in 0..KILLERS {
+                        if let Some(mov) =
+                            self.killers[index].take().and_then(|killer| {
+                                take(&mut self.moves, |&mov| mov == killer)
+                            })
+                        {
+                            return Some(mov);
+                        }
+                    }
+                    self.stage = Stage::Quiet;
+                    let color = self.color;
+                    self.scored =
+                        score_all(std::mem::take(&mut self.moves), |mov| {
+                            heuristics.history(color, mov)
+                        });
+                }
+                Stage::Quiet => match pop_best(&mut self.scored) {
+                    Some(mov) => return Some(mov),
+                    None => self.stage = Stage::Losing,
+                },
+                Stage::Losing => match pop_best(&mut self.losing) {
+                    Some(mov) => return Some(mov),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+}
+
+/// Removes the first of `moves` matching `predicate` and returns it
+fn take(
+    moves: &mut Vec<Move>,
+    predicate: impl FnMut(&Move) -> bool,
+) -> Option<Move> {
+    let index = moves.iter().position(predicate)?;
+    Some(moves.remove(index))
+}
+
+fn score_all(
+    moves: Vec<Move>,
+    mut score: impl FnMut(Move) -> Score,
+) -> Vec<(Score, Move)> {
+    moves.into_iter().map(|mov| (score(mov), mov)).collect()
+}
+
+/// Removes the move with the highest score. Usually only a few moves are
+/// tried before a cutoff, so this is cheaper than sorting all of them
+fn pop_best(scored: &mut Vec<(Score, Move)>) -> Option<Move> {
+    let index = scored
+        .iter()
+        .enumerate()
+        // The first of equally good moves, to keep the order stable
+        .max_by_key(|&(index, &(score, _))| (score, std::cmp::Reverse(index)))?
+        .0;
+    Some(scored.remove(index).1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Position;
+
+    fn picked(
+        position: &Position,
+        best_known: Option<Move>,
+        quiet: bool,
+        heuristics: &Heuristics,
+    ) -> Vec<String> {
+        let mut picker = MovePicker::new(
+            best_known,
+            quiet,
+            position.side_to_move,
+            3,
+            heuristics,
+        );
+        let mut moves = Vec::new();
+        while let Some(mov) = picker.next(&position.board, heuristics) {
+            moves.push(mov.to_uci());
+        }
+        moves
+    }
+
+    #[test]
+    fn stages() {
+        // The rook on d3 can be taken by the pawn and the queen, the pawn on
+        // h5 by the queen, but it is defended
+        let position =
+            Position::from_fen("6k1/8/6p1/7p/8/3r4/2P5/3Q2K1 w - - 0 1")
+                .unwrap();
+        let mut heuristics = Heuristics::new();
+        let quiet = position.parse_uci("c2c3").unwrap();
+        let killer = position.parse_uci("g1h2").unwrap();
+        heuristics.record_cutoff(&position.board, Color::White, quiet, 0, 4);
+        heuristics.record_cutoff(&position.board, Color::White, killer, 3, 1);
+        let best_known = position.parse_uci("d1e2").unwrap();
+        let moves = picked(&position, Some(best_known), true, &heuristics);
+        assert_eq!(moves[..5], ["d1e2", "c2d3", "d1d3", "g1h2", "c2c3"]);
+        assert_eq!(moves.last().unwrap(), "d1h5");
+        // Every legal move exactly once
+        let mut sorted = moves.clone();
+        sorted.sort();
+        let mut legal: Vec<String> = position
+            .legal_moves()
+            .iter()
+            .map(|mov| mov.to_uci())
+            .collect();
+        legal.sort();
+        assert_eq!(sorted, legal);
+        // A move of another position from the table is skipped
+        let illegal = Move::new((0, 0), (0, 7));
+        let moves = picked(&position, Some(illegal), true, &heuristics);
+        assert_eq!(moves[..2], ["c2d3", "d1d3"]);
+        assert_eq!(moves.len(), legal.len());
+        // Only captures and promotions, the losing ones last
+        let moves = picked(&position, Some(best_known), false, &heuristics);
+        assert_eq!(moves, ["c2d3", "d1d3", "d1h5"]);
+        // Captures aren't killers
+        let capture = position.parse_uci("d1d3").unwrap();
+        heuristics.record_cutoff(&position.board, Color::White, capture, 3, 1);
+        assert_eq!(heuristics.killers(3), [Some(killer), None]);
+    }
+}
@@ -0,0 +1,270 @@
+//! The evaluation of the pawn structure. Pawns move rarely and never back, so
+//! the same structure comes up again and again during a search, and what
+//! only depends on the pawns is cached in a pawn hash table
+
+use crate::{board::*, figures::*};
+
+/// Entries of a pawn hash table, a power of two
+const TABLE_SIZE: usize = 1 << 12;
+
+/// For each pawn behind another pawn of its color on the same column
+const DOUBLED: (Score, Score) = (-10, -20);
+/// For each pawn without pawns of its color on the columns next to it
+const ISOLATED: (Score, Score) = (-10, -15);
+/// For each pawn behind the pawns of its color on the columns next to it,
+/// which can't advance without being captured by an opposing pawn
+const BACKWARD: (Score, Score) = (-8, -10);
+/// For each pawn defended by a pawn or next to one
+const CONNECTED: (Score, Score) = (8, 12);
+
+/// For passed pawns by the number of rows they advanced from their back row,
+/// as (midgame, endgame)
+const PASSED: [(Score, Score); 8] = [
+    (0, 0),
+    (5, 10),
+    (5, 15),
+    (10, 25),
+    (20, 45),
+    (35, 70),
+    (60, 110),
+    (0, 0),
+];
+
+/// Added for passed pawns with nothing on the positions in front of them.
+/// Once the pieces are off, nothing else can stop them
+const FREE_PASSED: [(Score, Score); 8] = [
+    (0, 0),
+    (0, 0),
+    (0, 5),
+    (0, 10),
+    (5, 20),
+    (10, 35),
+    (20, 60),
+    (0, 0),
+];
+
+/// What is known about a pawn structure
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+struct PawnEntry {
+    key: u64,
+    /// The scores of the pawns of each color as (midgame, endgame), indexed
+    /// by `Color::index`
+    scores: [(Score, Score); 2],
+    /// The positions of passed pawns of both colors, as bits indexed by
+    /// column * 8 + row
+    passed: u64,
+}
+
+/// Caches pawn structure evaluations by the pawn hash of the board. Each
+/// search thread has its own, so no synchronization is needed
+pub struct PawnTable {
+    entries: Vec<Option<PawnEntry>>,
+}
+
+impl PawnTable {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![None; TABLE_SIZE],
+        }
+    }
+
+    /// The scores of the pawns of each color on `board` as (midgame,
+    /// endgame), indexed by `Color::index`. Each is positive if the pawns are
+    /// good for their color
+    pub fn evaluate(&mut self, board: &Board) -> [(Score, Score); 2] {
+        let slot = &mut self.entries[board.pawn_hash as usize % TABLE_SIZE];
+        let entry = match *slot {
+            Some(entry) if entry.key == board.pawn_hash => entry,
+            _ => *slot.insert(structure(board)),
+        };
+        let mut scores = entry.scores;
+        // Whether the way is free depends on the other pieces as well
+        for x in 0..8 {
+            for y in 0..8 {
+                if entry.passed & 1 << (x * 8 + y) == 0 {
+                    continue;
+                }
+                let color = board.get((x, y)).unwrap().color;
+                let forwards = color.forwards();
+                let mut ahead = (x, y + forwards);
+                let mut free = true;
+                while board.is_inside(ahead) {
+                    free &= board.get(ahead).is_none();
+                    ahead.1 += forwards;
+                }
+                if free {
+                    let (midgame, endgame) = FREE_PASSED[advanced(color, y)];
+                    let score = &mut scores[color.index()];
+                    score.0 += midgame;
+                    score.1 += endgame;
+                }
+            }
+        }
+        scores
+    }
+}
+
+impl Default for PawnTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The rows a pawn of `color` on row `y` advanced from its back row
+fn advanced(color: Color, y: i8) -> usize {
+    (y - color.back_row()).unsigned_abs() as usize
+}
+
+/// Evaluates the pawns of `board` without looking at the other pieces
+fn structure(board: &Board) -> PawnEntry {
+    let pawn_at = |pos: Pos, color: Color| {
+        board.is_inside(pos)
+            && board.get(pos) == Some(Piece::new(color, Figure::Pawn))
+    };
+    // Whether there is a pawn of `color` on column `x` with a row for which
+    // `rows` is true
+    let any_on_column = |x: i8, color: Color, rows: &dyn Fn(i8) -> bool| {
+        (0..8).any(|y| rows(y) && pawn_at((x, y), color))
+    };
+    let mut entry = PawnEntry {
+        key: board.pawn_hash,
+        ..PawnEntry::default()
+    };
+    let mut add = |color: Color, (midgame, endgame): (Score, Score)| {
+        let score = &mut entry.scores[color.index()];
+        score.0 += midgame;
+        score.1 += endgame;
+    };
+    let mut passed = 0;
+    for x in 0..8 {
+        for y in 0..8 {
+            let color = match board.get((x, y)) {
+                Some(Piece {
+                    color,
+                    figure: Figure::Pawn,
+                }) => color,
+                _ => continue,
+            };
+            let opponent = color.flipped();
+            let forwards = color.forwards();
+            let is_ahead = |row: i8| (row - y) * forwards > 0;
+            let neighbors = [x - 1, x + 1];
+            let doubled = any_on_column(x, color, &is_ahead);
+            if doubled {
+                add(color, DOUBLED);
+            }
+            let isolated = !neighbors
+                .iter()
+                .any(|&column| any_on_column(column, color, &|_| true));
+            let connected = neighbors.iter().any(|&column| {
+                pawn_at((column, y), color)
+                    || pawn_at((column, y - forwards), color)
+            });
+            if isolated {
+                add(color, ISOLATED);
+            } else if connected {
+                add(color, CONNECTED);
+            } else {
+                // Advancing would only be safe with support from behind
+                let stop = (x, y + forwards);
+                let supportable = neighbors.iter().any(|&column| {
+                    any_on_column(column, color, &|row| !is_ahead(row))
+                });
+                let stop_attacked = neighbors.iter().any(|&column| {
+                    pawn_at((column, stop.1 + forwards), opponent)
+                });
+                if !supportable && stop_attacked {
+                    add(color, BACKWARD);
+                }
+            }
+            let blocked = (x - 1..=x + 1)
+                .any(|column| any_on_column(column, opponent, &is_ahead));
+            // Of doubled pawns, only the front one is passed
+            if !blocked && !doubled {
+                add(color, PASSED[advanced(color, y)]);
+                passed |= 1 << (x * 8 + y);
+            }
+        }
+    }
+    entry.passed = passed;
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Position;
+
+    /// The score of the pawns of white minus the one of black
+    fn pawn_score(fen: &str) -> (Score, Score) {
+        let board = Position::from_fen(fen).unwrap().board;
+        let [white, black] = PawnTable::new().evaluate(&board);
+        (white.0 - black.0, white.1 - black.1)
+    }
+
+    #[test]
+    fn structures() {
+        // Doubled and isolated pawns against a healthy chain
+        let (midgame, endgame) =
+            pawn_score("4k3/1ppp4/8/8/8/2P5/2P5/4K3 w - - 0 1");
+        assert!(midgame < 0 && endgame < 0);
+        // The same pawns are worth more when connected
+        assert!(
+            pawn_score("4k3/8/8/8/8/3P4/4P3/4K3 w - - 0 1")
+                > pawn_score("4k3/8/8/8/8/2P5/6P1/4K3 w - - 0 1")
+        );
+        // Passed pawns count more the further they are
+        assert!(
+            pawn_score("4k3/8/1P6/8/8/8/8/4K3 w - - 0 1").1
+                > pawn_score("4k3/8/8/8/1P6/8/8/4K3 w - - 0 1").1
+        );
+        // A piece in the way of a passed pawn
+        assert!(
+            pawn_score("4k3/8/1P6/8/8/8/8/4K3 w - - 0 1")
+                > pawn_score("1n2k3/8/1P6/8/8/8/8/4K3 w - - 0 1")
+        );
+        // The pawn on d6 can't advance safely once e4 attacks d5
+        let backward = pawn_score("4k3/8/3p4/2p5/4P3/8/8/4K3 w - - 0 1");
+        let free = pawn_score("4k3/8/3p4/2p5/8/4P3/8/4K3 w - - 0 1");
+        assert_eq!(
+            (backward.0 - free.0, backward.1 - free.1),
+            (-BACKWARD.0, -BACKWARD.1)
+        );
+    }
+
+    #[test]
+    fn doubled_passed_pawns() {
+        let board = Position::from_fen("4k3/8/1P6/8/1P6/8/8/4K3 w - - 0 1")
+            .unwrap()
+            .board;
+        // Only the pawn on b6
+        assert_eq!(structure(&board).passed, 1 << (8 + 2));
+        let single = pawn_score("4k3/8/1P6/8/8/8/8/4K3 w - - 0 1");
+        let doubled = pawn_score("4k3/8/1P6/8/1P6/8/8/4K3 w - - 0 1");
+        assert_eq!(
+            (doubled.0 - single.0, doubled.1 - single.1),
+            (DOUBLED.0 + ISOLATED.0, DOUBLED.1 + ISOLATED.1)
+        );
+    }
+
+    #[test]
+    fn cached_by_the_pawns() {
+        let mut table = PawnTable::new();
+        let mut position =
+            Position::from_fen("4k3/ppp5/8/8/8/8/PP6/4K1N1 w - - 0 1").unwrap();
+        let before = table.evaluate(&position.board);
+        let pawn_hash = position.board.pawn_hash;
+        position.make_move(position.parse_uci("g1f3").unwrap());
+        assert_eq!(position.board.pawn_hash, pawn_hash);
+        assert_eq!(table.evaluate(&position.board), before);
+        position.make_move(position.parse_uci("a7a5").unwrap());
+        assert_ne!(position.board.pawn_hash, pawn_hash);
+        // Mirrored pawns score the same for the other color
+        let [white, black] = PawnTable::new().evaluate(
+            &Position::from_fen("4k3/pp6/8/8/8/8/PPP5/4K3 w - - 0 1")
+                .unwrap()
+                .board,
+        );
+        assert_eq!([black, white], before);
+    }
+}
@@ -0,0 +1,738 @@
+//! Reading and writing games in Portable Game Notation
+
+use std::fmt;
+
+use crate::{
+    board::{Move, Score},
+    fen::{FenError, START_FEN},
+    figures::Color,
+    notation::NotationError,
+    position::{GameStatus, Position},
+    score::Eval,
+};
+
+/// The tags every PGN game has, in the order they are written
+const SEVEN_TAG_ROSTER: [&str; 7] =
+    ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+const MAX_LINE_LENGTH: usize = 79;
+
+#[derive(Clone, Debug)]
+pub struct PgnGame {
+    /// All tag pairs in the order they appeared
+    pub tags: Vec<(String, String)>,
+    /// The position the game started in, given by the FEN tag if present
+    pub start: Position,
+    pub moves: Vec<PgnMove>,
+    /// "1-0", "0-1", "1/2-1/2" or "*"
+    pub result: String,
+    /// Comments that don't belong to any move, e.g. in a game without moves
+    pub comments: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct PgnMove {
+    pub mov: Move,
+    pub san: String,
+    pub comments_before: Vec<String>,
+    pub comments_after: Vec<String>,
+    /// Numeric annotation glyphs, `!` is 1, `?` is 2 and so on
+    pub nags: Vec<u8>,
+    /// Alternatives to this move, each starting in the position before it
+    pub variations: Vec<Vec<PgnMove>>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum PgnErrorKind {
+    UnterminatedTag,
+    UnterminatedComment,
+    UnbalancedVariation,
+    /// A variation before the first move of a line
+    VariationWithoutMove,
+    /// `--`, which some programs write for passing. Games can't contain it
+    NullMove,
+    Fen(FenError),
+    Move(NotationError),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct PgnError {
+    pub line: usize,
+    pub kind: PgnErrorKind,
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            PgnErrorKind::UnterminatedTag => write!(f, "unterminated tag"),
+            PgnErrorKind::UnterminatedComment => {
+                write!(f, "unterminated comment")
+            }
+            PgnErrorKind::UnbalancedVariation => {
+                write!(f, "unbalanced parentheses")
+            }
+            PgnErrorKind::VariationWithoutMove => {
+                write!(f, "variation before the first move")
+            }
+            PgnErrorKind::NullMove => write!(f, "null moves aren't supported"),
+            PgnErrorKind::Fen(err) => write!(f, "bad FEN tag: {}", err),
+            PgnErrorKind::Move(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    VariationStart,
+    VariationEnd,
+    Result(String),
+    Move(String),
+    NullMove,
+}
+
+/// Splits PGN text into tokens, each with the line it starts on
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, PgnError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    let mut at_line_start = true;
+    while let Some(c) = chars.next() {
+        let start_line = line;
+        let error = |kind| PgnError {
+            line: start_line,
+            kind,
+        };
+        if c == '\n' {
+            line += 1;
+            at_line_start = true;
+            continue;
+        }
+        if at_line_start && c == '%' {
+            // Escaped line, ignored
+            while chars.peek().is_some_and(|&c| c != '\n') {
+                chars.next();
+            }
+            continue;
+        }
+        at_line_start = false;
+        match c {
+            c if c.is_whitespace() => {}
+            '[' => {
+                let mut content = String::new();
+                let mut in_string = false;
+                loop {
+                    match chars.next() {
+                        Some('\\') if in_string => {
+                            content.extend(chars.next());
+                        }
+                        Some('"') => {
+                            in_string = !in_string;
+                            content.push('"');
+                        }
+                        Some(']') if !in_string => break,
+                        Some('\n') | None => {
+                            return Err(error(PgnErrorKind::UnterminatedTag))
+                        }
+                        Some(c) => content.push(c),
+                    }
+                }
+                let (name, value) = content
+                    .split_once('"')
+                    .ok_or_else(|| error(PgnErrorKind::UnterminatedTag))?;
+                tokens.push((
+                    start_line,
+                    Token::Tag(
+                        name.trim().to_string(),
+                        value.trim_end().trim_end_matches('"').to_string(),
+                    ),
+                ));
+            }
+            '{' => {
+                let mut comment = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            comment.push(c);
+                        }
+                        None => {
+                            return Err(error(
+                                PgnErrorKind::UnterminatedComment,
+                            ))
+                        }
+                    }
+                }
+                let comment =
+                    comment.split_whitespace().collect::<Vec<_>>().join(" ");
+                tokens.push((start_line, Token::Comment(comment)));
+            }
+            ';' => {
+                let mut comment = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    comment.push(c);
+                    chars.next();
+                }
+                tokens.push((
+                    start_line,
+                    Token::Comment(comment.trim().to_string()),
+                ));
+            }
+            '(' => tokens.push((start_line, Token::VariationStart)),
+            ')' => tokens.push((start_line, Token::VariationEnd)),
+            _ => {
+                let mut symbol = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{}()[];".contains(c) {
+                        break;
+                    }
+                    symbol.push(c);
+                    chars.next();
+                }
+                tokens.extend(
+                    split_symbol(&symbol)
+                        .into_iter()
+                        .map(|token| (start_line, token)),
+                );
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Evaluation symbols written instead of their numeric annotation glyphs
+const EVALUATION_SYMBOLS: [(&str, u8); 12] = [
+    ("=", 10),
+    ("\u{221e}", 13),
+    ("+=", 14),
+    ("+/=", 14),
+    ("=+", 15),
+    ("=/+", 15),
+    ("\u{b1}", 16),
+    ("+/-", 16),
+    ("\u{2213}", 17),
+    ("-/+", 17),
+    ("+-", 18),
+    ("-+", 19),
+];
+
+/// Turns a symbol like "12.Nf3!?", "$14" or "1-0" into tokens
+fn split_symbol(symbol: &str) -> Vec<Token> {
+    if let Some(nag) = symbol.strip_prefix('$') {
+        return nag.parse().map(Token::Nag).into_iter().collect();
+    }
+    if let Some(&(_, nag)) =
+        EVALUATION_SYMBOLS.iter().find(|&&(text, _)| text == symbol)
+    {
+        return vec![Token::Nag(nag)];
+    }
+    // Some write en passant captures like "exd6 e.p." or "exd6e.p."
+    let symbol = match symbol.strip_suffix("e.p.") {
+        Some("") => return Vec::new(),
+        Some(mov) => mov,
+        None => symbol,
+    };
+    if ["1-0", "0-1", "1/2-1/2", "*"].contains(&symbol) {
+        return vec![Token::Result(symbol.to_string())];
+    }
+    if symbol.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        // A move number
+        return Vec::new();
+    }
+    // Drop move numbers like "12." or "12..."
+    let digits = symbol.trim_start_matches(|c: char| c.is_ascii_digit());
+    let symbol = match digits.trim_start_matches('.') {
+        rest if rest.len() < digits.len() => rest,
+        _ => symbol,
+    };
+    if symbol.is_empty() {
+        return Vec::new();
+    }
+    if symbol == "--" {
+        return vec![Token::NullMove];
+    }
+    let annotation_start = symbol.trim_end_matches(['!', '?']).len();
+    let (mov, annotation) = symbol.split_at(annotation_start);
+    let mut tokens = Vec::new();
+    if !mov.is_empty() {
+        tokens.push(Token::Move(mov.to_string()));
+    }
+    let nag = match annotation {
+        "" => None,
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    };
+    tokens.extend(nag.map(Token::Nag));
+    tokens
+}
+
+/// Parses all games in `text`
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let tokens = tokenize(text)?;
+    let mut games = Vec::new();
+    let mut index = 0;
+    while index < tokens.len() {
+        let mut tags = Vec::new();
+        while let Some((_, Token::Tag(name, value))) = tokens.get(index) {
+            tags.push((name.clone(), value.clone()));
+            index += 1;
+        }
+        let fen = tags
+            .iter()
+            .find(|(name, _)| name == "FEN")
+            .map_or(START_FEN, |(_, value)| value);
+        let start = Position::from_fen(fen).map_err(|err| PgnError {
+            line: tokens.get(index.max(1) - 1).map_or(1, |&(line, _)| line),
+            kind: PgnErrorKind::Fen(err),
+        })?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            index,
+        };
+        let mut comments = Vec::new();
+        let moves = parser.parse_line(&mut start.clone(), &mut comments)?;
+        if let Some((line, Token::VariationEnd)) = tokens.get(parser.index) {
+            return Err(PgnError {
+                line: *line,
+                kind: PgnErrorKind::UnbalancedVariation,
+            });
+        }
+        index = parser.index;
+        let tag_result = tags
+            .iter()
+            .find(|(name, _)| name == "Result")
+            .map(|(_, value)| value.clone());
+        let result = match tokens.get(index) {
+            Some((_, Token::Result(result))) => {
+                index += 1;
+                result.clone()
+            }
+            _ => tag_result.unwrap_or_else(|| "*".to_string()),
+        };
+        games.push(PgnGame {
+            tags,
+            start,
+            moves,
+            result,
+            comments,
+        });
+    }
+    Ok(games)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    index: usize,
+}
+
+impl Parser<'_> {
+    /// Parses moves starting in `position` until the end of the variation or
+    /// game. Comments not followed by a move end up in `trailing_comments`
+    fn parse_line(
+        &mut self,
+        position: &mut Position,
+        trailing_comments: &mut Vec<String>,
+    ) -> Result<Vec<PgnMove>, PgnError> {
+        let tokens = self.tokens;
+        let mut moves: Vec<PgnMove> = Vec::new();
+        let mut pending_comments = Vec::new();
+        while let Some((line, token)) = tokens.get(self.index) {
+            let error = |kind| PgnError { line: *line, kind };
+            match token {
+                Token::Tag(..) | Token::Result(_) | Token::VariationEnd => {
+                    break
+                }
+                Token::NullMove => return Err(error(PgnErrorKind::NullMove)),
+                Token::Move(text) => {
+                    let mov = position
+                        .parse_move(text)
+                        .map_err(|err| error(PgnErrorKind::Move(err)))?;
+                    moves.push(PgnMove {
+                        mov,
+                        san: position.to_san(mov),
+                        comments_before: std::mem::take(&mut pending_comments),
+                        comments_after: Vec::new(),
+                        nags: Vec::new(),
+                        variations: Vec::new(),
+                    });
+                    position.make_move(mov);
+                }
+                Token::Comment(comment) => match moves.last_mut() {
+                    Some(last) if pending_comments.is_empty() => {
+                        last.comments_after.push(comment.clone())
+                    }
+                    _ => pending_comments.push(comment.clone()),
+                },
+                Token::Nag(nag) => {
+                    // Annotations before the first move are meaningless
+                    if let Some(last) = moves.last_mut() {
+                        last.nags.push(*nag);
+                    }
+                }
+                Token::VariationStart => {
+                    let last = moves.last_mut().ok_or_else(|| {
+                        error(PgnErrorKind::VariationWithoutMove)
+                    })?;
+                    let mut before = position.clone();
+                    before.unmake_move();
+                    self.index += 1;
+                    let mut comments = Vec::new();
+                    let mut variation =
+                        self.parse_line(&mut before, &mut comments)?;
+                    match tokens.get(self.index) {
+                        Some((_, Token::VariationEnd)) => {}
+                        _ => {
+                            return Err(error(
+                                PgnErrorKind::UnbalancedVariation,
+                            ))
+                        }
+                    }
+                    if let Some(last) = variation.last_mut() {
+                        last.comments_after.extend(comments);
+                    }
+                    if !variation.is_empty() {
+                        last.variations.push(variation);
+                    }
+                }
+            }
+            self.index += 1;
+        }
+        match moves.last_mut() {
+            Some(last) => last.comments_after.extend(pending_comments),
+            None => trailing_comments.extend(pending_comments),
+        }
+        Ok(moves)
+    }
+}
+
+impl PgnGame {
+    /// A game without moves starting in `start`
+    #[allow(dead_code)]
+    pub fn new(start: Position) -> Self {
+        Self {
+            tags: Vec::new(),
+            start,
+            moves: Vec::new(),
+            result: "*".to_string(),
+            comments: Vec::new(),
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The position after all moves of the main line
+    pub fn position(&self) -> Position {
+        let mut position = self.start.clone();
+        for mov in &self.moves {
+            position.make_move(mov.mov);
+        }
+        position
+    }
+
+    /// Appends a legal move to the main line
+    pub fn push(&mut self, mov: Move) -> &mut PgnMove {
+        let position = self.position();
+        self.moves.push(PgnMove {
+            mov,
+            san: position.to_san(mov),
+            comments_before: Vec::new(),
+            comments_after: Vec::new(),
+            nags: Vec::new(),
+            variations: Vec::new(),
+        });
+        self.moves.last_mut().unwrap()
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let mut result = String::new();
+        for &name in &SEVEN_TAG_ROSTER {
+            let value = match name {
+                "Result" => self.result.as_str(),
+                _ => self.tag(name).unwrap_or("?"),
+            };
+            result.push_str(&format_tag(name, value));
+        }
+        let fen = self.start.to_fen();
+        let mut extra_tags: Vec<(String, String)> = self
+            .tags
+            .iter()
+            .filter(|(name, _)| !SEVEN_TAG_ROSTER.contains(&name.as_str()))
+            .cloned()
+            .collect();
+        if fen != START_FEN && self.tag("FEN").is_none() {
+            extra_tags.push(("SetUp".to_string(), "1".to_string()));
+            extra_tags.push(("FEN".to_string(), fen));
+        }
+        for (name, value) in &extra_tags {
+            result.push_str(&format_tag(name, value));
+        }
+        result.push('\n');
+
+        let mut words = Vec::new();
+        for comment in &self.comments {
+            words.extend(comment_words(comment));
+        }
+        write_line(&self.moves, &self.start, &mut words);
+        words.push(self.result.clone());
+        let mut line = String::new();
+        for word in words {
+            if !line.is_empty() && line.len() + 1 + word.len() > MAX_LINE_LENGTH
+            {
+                result.push_str(&line);
+                result.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        result.push_str(&line);
+        result.push_str("\n\n");
+        result
+    }
+}
+
+impl GameStatus {
+    /// The result of the game as written in PGN
+    pub fn pgn_result(self) -> &'static str {
+        match self {
+            Self::Ongoing => "*",
+            Self::Checkmate {
+                winner: Color::White,
+            } => "1-0",
+            Self::Checkmate {
+                winner: Color::Black,
+            } => "0-1",
+            _ => "1/2-1/2",
+        }
+    }
+}
+
+impl PgnMove {
+    /// Adds an engine evaluation comment like "+0.35/4" or "+M3/6" for a
+    /// mate in 3 moves, with `score` in the view of the side that made the
+    /// move
+    pub fn add_eval(&mut self, score: Score, depth: usize) {
+        let eval = match Eval::from(score) {
+            Eval::Centipawns(centipawns) => {
+                format!("{:+.2}", centipawns as f64 / 100.0)
+            }
+            Eval::MateIn(moves) => format!("+M{}", moves),
+            Eval::MatedIn(moves) => format!("-M{}", moves),
+        };
+        self.comments_after.push(format!("{}/{}", eval, depth));
+    }
+}
+
+fn format_tag(name: &str, value: &str) -> String {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("[{} \"{}\"]\n", name, value)
+}
+
+/// A comment as words, so that long comments can be broken into lines
+fn comment_words(comment: &str) -> Vec<String> {
+    let comment = format!("{{{}}}", comment.replace('}', ")"));
+    comment.split_whitespace().map(str::to_string).collect()
+}
+
+/// Writes the moves of a line and its variations as separate words
+fn write_line(moves: &[PgnMove], start: &Position, words: &mut Vec<String>) {
+    let mut position = start.clone();
+    // Black's moves only need a number after an interruption
+    let mut needs_number = true;
+    for mov in moves {
+        for comment in &mov.comments_before {
+            words.extend(comment_words(comment));
+        }
+        needs_number |= !mov.comments_before.is_empty();
+        match position.side_to_move {
+            Color::White => {
+                words.push(format!("{}. {}", position.fullmove_number, mov.san))
+            }
+            Color::Black if needs_number => words
+                .push(format!("{}... {}", position.fullmove_number, mov.san)),
+            Color::Black => words.push(mov.san.clone()),
+        }
+        words.extend(mov.nags.iter().map(|nag| format!("${}", nag)));
+        for comment in &mov.comments_after {
+            words.extend(comment_words(comment));
+        }
+        for variation in &mov.variations {
+            let mut variation_words = Vec::new();
+            write_line(variation, &position, &mut variation_words);
+            if let Some(first) = variation_words.first_mut() {
+                first.insert(0, '(');
+            }
+            if let Some(last) = variation_words.last_mut() {
+                last.push(')');
+            }
+            words.extend(variation_words);
+        }
+        needs_number =
+            !mov.comments_after.is_empty() || !mov.variations.is_empty();
+        position.make_move(mov.mov);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSY: &str = "\u{feff}[Event \"Casual \\\"blitz\\\" game\"]\r
+[Site \"?\"]\r
+[White \"A\"]\r
+[Black \"B\"]\r
+\r
+{Opening comment} 1.e4 e5 2. Nf3!? Nc6 $14 (2...d6 {Philidor} 3.d4 (3. Bc4\r
+Be7) exd4) ; rest of line comment\r
+3.Bb5 a6 4.Ba4 Nf6 5.0-0 1-0\r
+\r
+% escaped line\r
+[Event \"Second\"]\r
+[FEN \"4k3/1P6/8/8/8/8/8/4K3 w - - 0 1\"]\r
+1. b8=N Kd8 2. Nc6+ *\r
+[Event \"No result\"]\r
+1. d4 d5\r
+";
+
+    #[test]
+    fn parse_messy() {
+        let games = parse_pgn(MESSY).unwrap();
+        assert_eq!(games.len(), 3);
+
+        let game = &games[0];
+        assert_eq!(game.tag("Event"), Some("Casual \"blitz\" game"));
+        assert_eq!(game.result, "1-0");
+        let sans: Vec<&str> =
+            game.moves.iter().map(|mov| mov.san.as_str()).collect();
+        assert_eq!(
+            sans,
+            ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6", "O-O"]
+        );
+        assert_eq!(game.moves[0].comments_before, ["Opening comment"]);
+        assert_eq!(game.moves[2].nags, [5]);
+        let knight = &game.moves[3];
+        assert_eq!(knight.nags, [14]);
+        assert_eq!(knight.variations.len(), 1);
+        let variation = &knight.variations[0];
+        assert_eq!(variation.len(), 3);
+        assert_eq!(variation[0].san, "d6");
+        assert_eq!(variation[0].comments_after, ["Philidor"]);
+        assert_eq!(variation[1].variations[0][1].san, "Be7");
+        assert_eq!(variation[2].san, "exd4");
+        assert_eq!(knight.comments_after, ["rest of line comment"]);
+
+        let game = &games[1];
+        assert_eq!(game.moves.len(), 3);
+        assert_eq!(game.moves[2].san, "Nc6+");
+        assert_eq!(game.result, "*");
+
+        assert_eq!(games[2].moves.len(), 2);
+        assert_eq!(games[2].result, "*");
+    }
+
+    #[test]
+    fn write_and_read_again() {
+        for game in parse_pgn(MESSY).unwrap() {
+            let text = game.to_pgn();
+            let again = parse_pgn(&text).unwrap();
+            assert_eq!(again.len(), 1, "{}", text);
+            assert_eq!(again[0].moves, game.moves, "{}", text);
+            assert_eq!(again[0].result, game.result);
+            assert_eq!(again[0].start.to_fen(), game.start.to_fen());
+            assert!(text.lines().all(|line| line.len() <= MAX_LINE_LENGTH));
+        }
+    }
+
+    #[test]
+    fn annotations_of_other_programs() {
+        let games =
+            parse_pgn("1. e4 d5 2. e5 f5 3. exf6 e.p. +/- Nxf6 = 4. d4 -+")
+                .unwrap();
+        let moves = &games[0].moves;
+        assert_eq!(moves[4].san, "exf6");
+        assert_eq!(moves[4].nags, [16]);
+        assert_eq!(moves[5].nags, [10]);
+        assert_eq!(moves[6].nags, [19]);
+        let games = parse_pgn("1. e4 d5 2. e5 f5 3. exf6e.p.").unwrap();
+        assert_eq!(games[0].moves[4].san, "exf6");
+        let error = parse_pgn("1. e4 --").unwrap_err();
+        assert_eq!(error.kind, PgnErrorKind::NullMove);
+    }
+
+    #[test]
+    fn long_comments_are_wrapped() {
+        let mut game = PgnGame::new(Position::new());
+        let comment = "a long comment ".repeat(20).trim().to_string();
+        game.comments.push(comment.clone());
+        let e4 = Position::new().parse_san("e4").unwrap();
+        game.push(e4).comments_after.push(comment.clone());
+        let text = game.to_pgn();
+        assert!(text.lines().all(|line| line.len() <= MAX_LINE_LENGTH));
+        let again = parse_pgn(&text).unwrap();
+        // Read back, the comment before the first move belongs to it
+        let e4 = &again[0].moves[0];
+        assert_eq!(e4.comments_before, e4.comments_after);
+        assert_eq!(e4.comments_after, [comment]);
+    }
+
+    #[test]
+    fn writing() {
+        let mut game = PgnGame::new(Position::new());
+        game.tags.push(("White".to_string(), "Engine".to_string()));
+        let e4 = Position::new().parse_san("e4").unwrap();
+        game.push(e4).add_eval(crate::eval::PAWN_SCORE, 3);
+        let e5 = game.position().parse_san("e5").unwrap();
+        game.push(e5).add_eval(crate::score::mated_in(4), 4);
+        game.result = "1/2-1/2".to_string();
+        assert_eq!(
+            game.to_pgn(),
+            "[Event \"?\"]
+[Site \"?\"]
+[Date \"?\"]
+[Round \"?\"]
+[White \"Engine\"]
+[Black \"?\"]
+[Result \"1/2-1/2\"]
+
+1. e4 {+1.00/3} 1... e5 {-M2/4} 1/2-1/2
+
+"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = parse_pgn("[Event \"x\"]\n\n1. e4 e5\n2. Ke3").unwrap_err();
+        assert_eq!(error.line, 4);
+        assert!(matches!(error.kind, PgnErrorKind::Move(_)));
+        let error = parse_pgn("1. e4 (1. d4 d5").unwrap_err();
+        assert_eq!(error.kind, PgnErrorKind::UnbalancedVariation);
+        let error = parse_pgn("1. e4 {never closed").unwrap_err();
+        assert_eq!(error.kind, PgnErrorKind::UnterminatedComment);
+        let error = parse_pgn("(1. d4) 1. e4").unwrap_err();
+        assert_eq!(error.kind, PgnErrorKind::VariationWithoutMove);
+    }
+}
@@ -0,0 +1,445 @@
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ai, board::*, figures::*, zobrist};
+
+/// A board together with everything else needed to continue a game: whose
+/// turn it is, the clocks and the moves that led to it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "GameRecord", into = "GameRecord")]
+pub struct Position {
+    pub board: Board,
+    pub side_to_move: Color,
+    /// Number of half moves since the last capture or pawn move
+    pub halfmove_clock: u32,
+    /// Starts at 1 and is incremented after every move of black
+    pub fullmove_number: u32,
+    history: Vec<HistoryEntry>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum GameStatus {
+    Ongoing,
+    Checkmate {
+        winner: Color,
+    },
+    Stalemate,
+    /// Fifty moves by each side without a capture or pawn move
+    FiftyMoveRule,
+    ThreefoldRepetition,
+    /// Neither side has the pieces left to checkmate
+    InsufficientMaterial,
+}
+
+/// What is needed to take back a null move
+#[derive(Clone, Copy, Debug)]
+pub struct NullMove {
+    en_passant: Option<Pos>,
+    halfmove_clock: u32,
+}
+
+/// Everything needed to take back a move
+#[derive(Clone, Debug)]
+struct HistoryEntry {
+    mov: Move,
+    undo: Undo,
+    halfmove_clock: u32,
+}
+
+impl Position {
+    pub fn new() -> Self {
+        Self::from_board(Board::new(), Color::White)
+    }
+
+    pub fn from_board(mut board: Board, side_to_move: Color) -> Self {
+        // The fields of the board might have been changed directly
+        board.hash = board.compute_hash();
+        board.pawn_hash = board.compute_pawn_hash();
+        Self {
+            board,
+            side_to_move,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        ai::legal_moves(&self.board, self.side_to_move)
+    }
+
+    /// Plays `mov`, which has to be a legal move of the side to move
+    pub fn make_move(&mut self, mov: Move) {
+        debug_assert_eq!(
+            self.board.get(mov.from).map(|piece| piece.color),
+            Some(self.side_to_move),
+            "Bad move: {:?}",
+            mov
+        );
+        let resets_clock = self.board.get(mov.to).is_some()
+            || self.board.get(mov.from).unwrap().figure == Figure::Pawn;
+        let undo = self.board.do_move(mov);
+        self.history.push(HistoryEntry {
+            mov,
+            undo,
+            halfmove_clock: self.halfmove_clock,
+        });
+        debug_assert_eq!(self.board.hash, self.board.compute_hash());
+        debug_assert_eq!(self.board.pawn_hash, self.board.compute_pawn_hash());
+        self.halfmove_clock = if resets_clock {
+            0
+        } else {
+            self.halfmove_clock + 1
+        };
+        if self.side_to_move == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = self.side_to_move.flipped();
+    }
+
+    /// Takes back the last move and returns it, if there was one
+    pub fn unmake_move(&mut self) -> Option<Move> {
+        let entry = self.history.pop()?;
+        self.board.unmake(entry.mov, entry.undo);
+        self.halfmove_clock = entry.halfmove_clock;
+        self.side_to_move = self.side_to_move.flipped();
+        if self.side_to_move == Color::Black {
+            self.fullmove_number -= 1;
+        }
+        Some(entry.mov)
+    }
+
+    /// Passes the turn to the other side without moving, which isn't allowed
+    /// in chess but tells the search how good the position is even if the
+    /// opponent could move twice. Has to be taken back with
+    /// `unmake_null_move` before making any other move
+    pub fn make_null_move(&mut self) -> NullMove {
+        let null_move = NullMove {
+            en_passant: self.board.en_passant,
+            halfmove_clock: self.halfmove_clock,
+        };
+        self.board.hash ^=
+            zobrist::en_passant(self.board.capturable_en_passant());
+        self.board.en_passant = None;
+        // Repetitions through a null move don't count
+        self.halfmove_clock = 0;
+        self.side_to_move = self.side_to_move.flipped();
+        null_move
+    }
+
+    pub fn unmake_null_move(&mut self, null_move: NullMove) {
+        self.side_to_move = self.side_to_move.flipped();
+        self.halfmove_clock = null_move.halfmove_clock;
+        self.board.en_passant = null_move.en_passant;
+        self.board.hash ^=
+            zobrist::en_passant(self.board.capturable_en_passant());
+    }
+
+    pub fn status(&self) -> GameStatus {
+        if self.legal_moves().is_empty() {
+            if self.board.is_in_check(self.side_to_move) {
+                GameStatus::Checkmate {
+                    winner: self.side_to_move.flipped(),
+                }
+            } else {
+                GameStatus::Stalemate
+            }
+        } else {
+            self.draw_status().unwrap_or(GameStatus::Ongoing)
+        }
+    }
+
+    /// Checks the rules that end the game in a draw even though the side to
+    /// move still has moves left. Doesn't generate any moves, so this is cheap
+    /// enough to be called during the search
+    pub fn draw_status(&self) -> Option<GameStatus> {
+        if self.halfmove_clock >= 100 {
+            Some(GameStatus::FiftyMoveRule)
+        } else if self.repetitions() >= 3 {
+            Some(GameStatus::ThreefoldRepetition)
+        } else if self.board.has_insufficient_material() {
+            Some(GameStatus::InsufficientMaterial)
+        } else {
+            None
+        }
+    }
+
+    /// How often the current position occured in this game, including now
+    pub fn repetitions(&self) -> usize {
+        // Positions can only repeat with the same side to move and since the
+        // last capture or pawn move
+        let reversible = self.history.len().min(self.halfmove_clock as usize);
+        1 + self
+            .history
+            .iter()
+            .rev()
+            .take(reversible)
+            .skip(1)
+            .step_by(2)
+            .filter(|entry| entry.undo.hash == self.board.hash)
+            .count()
+    }
+
+    /// The Zobrist hash of the position: pieces, side to move, castling
+    /// rights and en passant position, but not the clocks or the history
+    pub fn hash(&self) -> u64 {
+        self.board.hash ^ zobrist::side_to_move(self.side_to_move)
+    }
+
+    /// The moves played so far, oldest first
+    pub fn moves(&self) -> impl Iterator<Item = Move> + '_ {
+        self.history.iter().map(|entry| entry.mov)
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The serialized form of a `Position`: the position the game started from
+/// and the moves played since, so that the whole history is restored
+#[derive(Serialize, Deserialize)]
+struct GameRecord {
+    cells: Cells,
+    /// Inferred from the positions of kings and rooks if missing
+    #[serde(default)]
+    castling: Option<CastlingRights>,
+    #[serde(default)]
+    en_passant: Option<Pos>,
+    side_to_move: Color,
+    #[serde(default)]
+    halfmove_clock: u32,
+    #[serde(default = "first_move")]
+    fullmove_number: u32,
+    #[serde(default)]
+    moves: Vec<Move>,
+}
+
+fn first_move() -> u32 {
+    1
+}
+
+impl TryFrom<GameRecord> for Position {
+    type Error = String;
+
+    fn try_from(record: GameRecord) -> Result<Self, Self::Error> {
+        let mut board = Board::from(record.cells);
+        if let Some(castling) = record.castling {
+            board.castling = castling;
+        }
+        board.en_passant = record.en_passant;
+        board.hash = board.compute_hash();
+        board.pawn_hash = board.compute_pawn_hash();
+        let mut position = Self {
+            board,
+            side_to_move: record.side_to_move,
+            halfmove_clock: record.halfmove_clock,
+            fullmove_number: record.fullmove_number,
+            history: Vec::new(),
+        };
+        for mov in record.moves {
+            if !position.legal_moves().contains(&mov) {
+                return Err(format!("Illegal move in game record: {:?}", mov));
+            }
+            position.make_move(mov);
+        }
+        Ok(position)
+    }
+}
+
+impl From<Position> for GameRecord {
+    fn from(mut position: Position) -> Self {
+        let moves: Vec<Move> = position.moves().collect();
+        while position.unmake_move().is_some() {}
+        Self {
+            cells: position.board.cells,
+            castling: Some(position.board.castling),
+            en_passant: position.board.en_passant,
+            side_to_move: position.side_to_move,
+            halfmove_clock: position.halfmove_clock,
+            fullmove_number: position.fullmove_number,
+            moves,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn make_and_unmake() {
+        let mut position = Position::new();
+        let moves = [
+            Move::new((4, 6), (4, 4)),
+            Move::new((6, 0), (5, 2)),
+            Move::new((6, 7), (5, 5)),
+        ];
+        for &mov in &moves {
+            position.make_move(mov);
+        }
+        assert_eq!(position.side_to_move, Color::Black);
+        assert_eq!(position.fullmove_number, 2);
+        assert_eq!(position.halfmove_clock, 2);
+        assert_eq!(position.moves().collect::<Vec<_>>(), moves);
+        for &mov in moves.iter().rev() {
+            assert_eq!(position.unmake_move(), Some(mov));
+        }
+        assert_eq!(position.unmake_move(), None);
+        assert_eq!(position.board, Board::new());
+        assert_eq!(position.side_to_move, Color::White);
+        assert_eq!(position.fullmove_number, 1);
+        assert_eq!(position.halfmove_clock, 0);
+    }
+
+    #[test]
+    fn null_moves() {
+        let mut position = Position::from_fen(
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2",
+        )
+        .unwrap();
+        let start = position.clone();
+        let null_move = position.make_null_move();
+        assert_eq!(position.side_to_move, Color::Black);
+        assert_eq!(position.board.en_passant, None);
+        assert_eq!(
+            position.hash(),
+            Position::from_fen(
+                "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2"
+            )
+            .unwrap()
+            .hash()
+        );
+        position.unmake_null_move(null_move);
+        assert_eq!(position.hash(), start.hash());
+        assert_eq!(position.board, start.board);
+        assert_eq!(position.halfmove_clock, start.halfmove_clock);
+    }
+
+    #[test]
+    fn unmake_restores_the_board() {
+        // Castling to both sides, captures, en passant and promotions
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N w - - 0 1",
+        ] {
+            let mut position = Position::from_fen(fen).unwrap();
+            let start = position.board.clone();
+            for mov in position.legal_moves() {
+                position.make_move(mov);
+                assert_eq!(position.unmake_move(), Some(mov));
+                assert_eq!(position.board, start, "{} {:?}", fen, mov);
+            }
+        }
+    }
+
+    fn play(position: &mut Position, moves: &[(Pos, Pos)]) {
+        for &(from, to) in moves {
+            let mov = Move::new(from, to);
+            assert!(position.legal_moves().contains(&mov), "{:?}", mov);
+            position.make_move(mov);
+        }
+    }
+
+    #[test]
+    fn checkmate_and_stalemate() {
+        let mut position = Position::new();
+        assert_eq!(position.status(), GameStatus::Ongoing);
+        // Fool's mate
+        play(
+            &mut position,
+            &[((5, 6), (5, 5)), ((4, 1), (4, 3)), ((6, 6), (6, 4))],
+        );
+        assert_eq!(position.status(), GameStatus::Ongoing);
+        play(&mut position, &[((3, 0), (7, 4))]);
+        assert_eq!(
+            position.status(),
+            GameStatus::Checkmate {
+                winner: Color::Black
+            }
+        );
+
+        let mut board = Board::empty();
+        board.set((0, 0), Some(Piece::new(Color::Black, Figure::King)));
+        board.set((1, 2), Some(Piece::new(Color::White, Figure::Queen)));
+        board.set((7, 7), Some(Piece::new(Color::White, Figure::King)));
+        let position = Position::from_board(board, Color::Black);
+        assert_eq!(position.status(), GameStatus::Stalemate);
+    }
+
+    #[test]
+    fn draws() {
+        let mut position = Position::new();
+        let knights = [
+            ((6, 7), (5, 5)),
+            ((6, 0), (5, 2)),
+            ((5, 5), (6, 7)),
+            ((5, 2), (6, 0)),
+        ];
+        play(&mut position, &knights);
+        assert_eq!(position.repetitions(), 2);
+        assert_eq!(position.status(), GameStatus::Ongoing);
+        play(&mut position, &knights);
+        assert_eq!(position.status(), GameStatus::ThreefoldRepetition);
+
+        // The first occurrence after a double step counts, as no pawn can hit
+        // en passant
+        let mut position = Position::new();
+        play(&mut position, &[((4, 6), (4, 4))]);
+        let knights = [
+            ((6, 0), (5, 2)),
+            ((6, 7), (5, 5)),
+            ((5, 2), (6, 0)),
+            ((5, 5), (6, 7)),
+        ];
+        play(&mut position, &knights);
+        assert_eq!(position.repetitions(), 2);
+        play(&mut position, &knights);
+        assert_eq!(position.status(), GameStatus::ThreefoldRepetition);
+
+        let mut position = Position::new();
+        position.halfmove_clock = 99;
+        play(&mut position, &[((6, 7), (5, 5))]);
+        assert_eq!(position.status(), GameStatus::FiftyMoveRule);
+
+        let mut board = Board::empty();
+        board.set((0, 0), Some(Piece::new(Color::Black, Figure::King)));
+        board.set((7, 7), Some(Piece::new(Color::White, Figure::King)));
+        board.set((2, 2), Some(Piece::new(Color::White, Figure::Bishop)));
+        board.set((5, 5), Some(Piece::new(Color::Black, Figure::Bishop)));
+        let position = Position::from_board(board.clone(), Color::White);
+        assert_eq!(position.status(), GameStatus::InsufficientMaterial);
+        board.set((5, 4), Some(Piece::new(Color::Black, Figure::Bishop)));
+        board.set((5, 5), None);
+        let position = Position::from_board(board, Color::White);
+        assert_eq!(position.status(), GameStatus::Ongoing);
+    }
+
+    #[test]
+    fn serialization_keeps_history() {
+        let mut position = Position::new();
+        position.make_move(Move::new((4, 6), (4, 4)));
+        position.make_move(Move::new((3, 1), (3, 3)));
+        let json = serde_json::to_string(&position).unwrap();
+        let mut restored: Position = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.board, position.board);
+        assert_eq!(restored.side_to_move, Color::White);
+        assert_eq!(restored.fullmove_number, 2);
+        assert_eq!(restored.moves().count(), 2);
+        restored.unmake_move();
+        restored.unmake_move();
+        assert_eq!(restored.board, Board::new());
+    }
+
+    #[test]
+    fn illegal_moves_are_rejected() {
+        let json = serde_json::to_string(&Position::new())
+            .unwrap()
+            .replace(r#""moves":[]"#, r#""moves":[{"from":[4,6],"to":[4,3]}]"#);
+        assert!(serde_json::from_str::<Position>(&json).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Scores of the search: Evaluations in the units of `eval::PAWN_SCORE` and
+//! mates, which are encoded as scores close to `MATE` so that they compare
+//! like all other scores, and the faster mate is the better one
+
+use std::fmt;
+
+use crate::{board::Score, eval::PAWN_SCORE};
+
+/// The score of mating right now. Mating later scores one less per ply, so
+/// that the fastest mate is preferred and being mated is delayed
+pub const MATE: Score = 100_000;
+
+/// Mates further from the root than this many plies can't be told apart from
+/// evaluations. Far more than the search reaches, even with extensions and
+/// quiescence search
+const MAX_MATE_PLY: Score = 1000;
+
+/// Scores above this (or below its negation) are mates
+pub const MATE_BOUND: Score = MATE - MAX_MATE_PLY;
+
+/// The score of mating `ply` plies from the root
+pub fn mate_in(ply: usize) -> Score {
+    MATE - ply as Score
+}
+
+/// The score of being mated `ply` plies from the root
+pub fn mated_in(ply: usize) -> Score {
+    -mate_in(ply)
+}
+
+pub fn is_mate(score: Score) -> bool {
+    score.abs() > MATE_BOUND
+}
+
+/// Mate scores count the plies from the root to the mate. To be stored, they
+/// have to count from the position `ply` plies from the root instead, as it
+/// may be reached at a different distance from the root later
+pub fn to_position(score: Score, ply: usize) -> Score {
+    if score > MATE_BOUND {
+        score + ply as Score
+    } else if score < -MATE_BOUND {
+        score - ply as Score
+    } else {
+        score
+    }
+}
+
+/// Turns a score stored by `to_position` back into one counting from the
+/// root, for a position `ply` plies from it
+pub fn from_position(score: Score, ply: usize) -> Score {
+    if score > MATE_BOUND {
+        score - ply as Score
+    } else if score < -MATE_BOUND {
+        score + ply as Score
+    } else {
+        score
+    }
+}
+
+/// A score as shown to humans and chess GUIs
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Eval {
+    Centipawns(i32),
+    /// The side to move mates in this many moves
+    MateIn(u32),
+    /// The side to move is mated in this many moves
+    MatedIn(u32),
+}
+
+impl From<Score> for Eval {
+    fn from(score: Score) -> Self {
+        let plies = (MATE - score.abs()) as u32;
+        if score > MATE_BOUND {
+            // The mating side makes the first and the last move
+            Eval::MateIn(plies.div_ceil(2))
+        } else if score < -MATE_BOUND {
+            Eval::MatedIn(plies / 2)
+        } else {
+            Eval::Centipawns(score * 100 / PAWN_SCORE)
+        }
+    }
+}
+
+/// Like `M3`, `-M2` or `35` for centipawns
+impl fmt::Display for Eval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Eval::Centipawns(centipawns) => write!(f, "{}", centipawns),
+            Eval::MateIn(moves) => write!(f, "M{}", moves),
+            Eval::MatedIn(moves) => write!(f, "-M{}", moves),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let eval = |score| Eval::from(score).to_string();
+        assert_eq!(eval(mate_in(1)), "M1");
+        assert_eq!(eval(mate_in(5)), "M3");
+        assert_eq!(eval(mated_in(4)), "-M2");
+        assert_eq!(eval(mated_in(0)), "-M0");
+        assert_eq!(eval(-3 * PAWN_SCORE), "-300");
+        assert_eq!(eval(0), "0");
+        assert!(mate_in(1) > mate_in(5) && mated_in(4) > mated_in(2));
+        assert!(is_mate(mate_in(999)) && !is_mate(MATE_BOUND));
+    }
+}
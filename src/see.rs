@@ -0,0 +1,140 @@
+//! Static exchange evaluation: What a capture wins once both sides have
+//! recaptured on the same position as long as it pays off for them
+
+use crate::{board::*, eval::value, figures::*};
+
+/// The material won by `mov` itself: the value of the captured piece and
+/// what a promotion adds
+pub fn material_gain(board: &Board, mov: Move) -> Score {
+    let captured = if board.is_en_passant(mov) {
+        Some(Figure::Pawn)
+    } else {
+        board.get(mov.to).map(|piece| piece.figure)
+    };
+    captured.map_or(0, value)
+        + mov
+            .promotion
+            .map_or(0, |figure| value(figure) - value(Figure::Pawn))
+}
+
+/// Whether `mov` wins at least the value of the piece it puts on `mov.to`,
+/// so that no exchange on that position can lose material. Cheaper than
+/// `see`, which only needs to be asked when this is false
+pub fn cannot_lose(board: &Board, mov: Move) -> bool {
+    board.get(mov.from).is_some_and(|moving| {
+        material_gain(board, mov)
+            >= value(mov.promotion.unwrap_or(moving.figure))
+    })
+}
+
+/// The material the side making `mov` wins when both sides keep capturing
+/// on `mov.to` with their least valuable piece, each side stopping when that
+/// is better for it. Sliders behind other pieces join in once the pieces in
+/// front of them have captured. Pins are ignored, so the result can be
+/// wrong when a recapturing piece is pinned
+pub fn see(board: &Board, mov: Move) -> Score {
+    let target = mov.to;
+    let moving = match board.get(mov.from) {
+        Some(piece) => piece,
+        None => return 0,
+    };
+    let mut board = board.clone();
+    // `gains[i]` is what the side making capture i wins, if nobody captures
+    // after it
+    let mut gains = vec![material_gain(&board, mov)];
+    if board.is_en_passant(mov) {
+        board.set((target.0, mov.from.1), None);
+    }
+    board.set(mov.from, None);
+    let mut on_target = value(mov.promotion.unwrap_or(moving.figure));
+    let mut side = moving.color.flipped();
+    while let Some((from, figure)) =
+        least_valuable_attacker(&board, target, side)
+    {
+        // The king can't capture a defended piece
+        if figure == Figure::King && board.is_attacked(target, side.flipped()) {
+            break;
+        }
+        gains.push(on_target - gains.last().unwrap());
+        on_target = value(figure);
+        board.set(from, None);
+        side = side.flipped();
+    }
+    // Going backwards, every side chooses between capturing and stopping
+    for i in (1..gains.len()).rev() {
+        gains[i - 1] = -(-gains[i - 1]).max(gains[i]);
+    }
+    gains[0]
+}
+
+fn least_valuable_attacker(
+    board: &Board,
+    target: Pos,
+    by: Color,
+) -> Option<(Pos, Figure)> {
+    let mut best: Option<(Pos, Figure)> = None;
+    board.attackers(target, by, |from| {
+        let figure = board.get(from).unwrap().figure;
+        if best.iter().all(|&(_, best)| value(figure) < value(best)) {
+            best = Some((from, figure));
+        }
+    });
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Position;
+
+    fn see_of(fen: &str, mov: &str) -> Score {
+        let position = Position::from_fen(fen).unwrap();
+        see(&position.board, position.parse_uci(mov).unwrap())
+    }
+
+    #[test]
+    fn exchanges() {
+        // The pawn is defended
+        assert_eq!(see_of("4k3/8/3p4/4p3/8/2B5/8/4K3 w - - 0 1", "c3e5"), -230);
+        // The pawn isn't
+        assert_eq!(see_of("4k3/8/8/4p3/8/2B5/8/4K3 w - - 0 1", "c3e5"), 100);
+        // The pawn wins the queen, but is taken back
+        assert_eq!(see_of("7k/8/8/3p4/4Q3/8/4R3/4K3 b - - 0 1", "d5e4"), 800);
+        // Quiet moves, into an attacked position and not
+        assert_eq!(see_of("4k3/8/8/6p1/8/8/8/2B1K3 w - - 0 1", "c1f4"), -330);
+        assert_eq!(see_of("4k3/8/8/6p1/8/8/8/2B1K3 w - - 0 1", "c1d2"), 0);
+        assert_eq!(
+            see_of("6k1/8/6p1/7p/8/3r4/2P5/3Q2K1 w - - 0 1", "d1h5"),
+            -800
+        );
+    }
+
+    #[test]
+    fn x_rays() {
+        // The second rook recaptures through the first one
+        assert_eq!(
+            see_of("4r1k1/8/8/4p3/8/8/4R3/4R1K1 w - - 0 1", "e2e5"),
+            100
+        );
+        // Without it, the rook is lost for a pawn
+        assert_eq!(see_of("4r1k1/8/8/4p3/8/8/4R3/6K1 w - - 0 1", "e2e5"), -400);
+        // The queen behind the rook defends as well
+        assert_eq!(see_of("4k3/3r4/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"), 100);
+        assert_eq!(
+            see_of("3qk3/3r4/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"),
+            -400
+        );
+    }
+
+    #[test]
+    fn special_moves() {
+        // The king can't take back a defended piece
+        assert_eq!(see_of("4k3/4p3/8/8/7Q/8/4R3/5K2 w - - 0 1", "e2e7"), 100);
+        assert_eq!(see_of("4k3/4p3/8/8/8/8/4R3/5K2 w - - 0 1", "e2e7"), -400);
+        // En passant
+        assert_eq!(see_of("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+        // Promotions, taken back or capturing
+        assert_eq!(see_of("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q"), -100);
+        assert_eq!(see_of("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q"), 1300);
+    }
+}
@@ -0,0 +1,272 @@
+//! The transposition table remembers the results of searched positions, so
+//! that positions reached by different move orders are only searched once
+
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::{board::*, figures::Figure, score};
+
+/// The size of the table if none is chosen
+pub const DEFAULT_SIZE_MB: usize = 16;
+
+/// Entries have room for the lowest 6 bits of the generation
+const GENERATION_MASK: u8 = 0x3f;
+
+/// How the stored score relates to the real score of the position
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bound {
+    /// The score is the real score
+    Exact = 1,
+    /// The real score is at least as high, the search was cut off
+    Lower = 2,
+    /// The real score is at most as high, no move reached alpha
+    Upper = 3,
+}
+
+/// What is known about a position
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Entry {
+    pub best_move: Option<Move>,
+    pub score: Score,
+    /// The number of plies the position was searched
+    pub depth: usize,
+    pub bound: Bound,
+}
+
+/// A slot of the table. The key is stored xored with the data, so that an
+/// entry torn by two threads writing at the same time doesn't match any
+/// position and no locking is needed
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+    /// Incremented with every search, so that entries of older searches are
+    /// replaced first
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    /// A table taking about `size_mb` megabytes
+    pub fn new(size_mb: usize) -> Self {
+        let count =
+            (size_mb * 1024 * 1024 / std::mem::size_of::<Slot>()).max(1);
+        Self {
+            slots: (0..count).map(|_| Slot::default()).collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    /// Forgets everything, e.g. before a new game
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Marks all entries as coming from older searches
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn slot(&self, hash: u64) -> &Slot {
+        &self.slots[(hash % self.slots.len() as u64) as usize]
+    }
+
+    /// Looks up the position with `hash`, which is `ply` plies from the root
+    pub fn probe(&self, hash: u64, ply: usize) -> Option<Entry> {
+        let slot = self.slot(hash);
+        let data = slot.data.load(Ordering::Relaxed);
+        if slot.key.load(Ordering::Relaxed) ^ data != hash {
+            return None;
+        }
+        let mut entry = unpack(data)?;
+        entry.score = score::from_position(entry.score, ply);
+        Some(entry)
+    }
+
+    /// Stores what is known about the position with `hash`, which is `ply`
+    /// plies from the root
+    pub fn store(&self, hash: u64, ply: usize, entry: Entry) {
+        let slot = self.slot(hash);
+        let generation =
+            self.generation.load(Ordering::Relaxed) & GENERATION_MASK;
+        let old_data = slot.data.load(Ordering::Relaxed);
+        let same_position = slot.key.load(Ordering::Relaxed) ^ old_data == hash;
+        // Prefer keeping deeper searches of the current search
+        if let Some(old) = unpack(old_data) {
+            if generation_of(old_data) == generation
+                && old.depth > entry.depth
+                && !(same_position && entry.bound == Bound::Exact)
+            {
+                return;
+            }
+        }
+        let entry = Entry {
+            score: score::to_position(entry.score, ply),
+            // Keep the best move if this search didn't find one
+            best_move: entry.best_move.or_else(|| {
+                if same_position {
+                    unpack(old_data).and_then(|old| old.best_move)
+                } else {
+                    None
+                }
+            }),
+            ..entry
+        };
+        let data = pack(entry, generation);
+        slot.key.store(hash ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+
+    /// The share of used slots in permill, as reported to chess GUIs
+    pub fn hashfull(&self) -> usize {
+        let sample = self.slots.len().min(1000);
+        let used = self.slots[..sample]
+            .iter()
+            .filter(|slot| slot.data.load(Ordering::Relaxed) != 0)
+            .count();
+        used * 1000 / sample
+    }
+}
+
+/// Packs an entry into the bits 0-15 for the move, 16-47 for the score, 48-55
+/// for the depth, 56-57 for the bound and 58-63 for the generation
+fn pack(entry: Entry, generation: u8) -> u64 {
+    let mov = entry.best_move.map_or(0, |mov| {
+        let promotion = mov.promotion.map_or(0, |figure| figure as u64 + 1);
+        mov.from.0 as u64
+            | (mov.from.1 as u64) << 3
+            | (mov.to.0 as u64) << 6
+            | (mov.to.1 as u64) << 9
+            | promotion << 12
+    });
+    mov | (entry.score as u32 as u64) << 16
+        | (entry.depth.min(255) as u64) << 48
+        | (entry.bound as u64) << 56
+        | ((generation & GENERATION_MASK) as u64) << 58
+}
+
+fn unpack(data: u64) -> Option<Entry> {
+    let bound = match data >> 56 & 3 {
+        1 => Bound::Exact,
+        2 => Bound::Lower,
+        3 => Bound::Upper,
+        // Empty
+        _ => return None,
+    };
+    let mov = data & 0xffff;
+    // A move can't go from a position to itself, so this means there is none
+    let best_move = if mov == 0 {
+        None
+    } else {
+        let promotion = match mov >> 12 {
+            0 => None,
+            figure => Some(FIGURES[figure as usize - 1]),
+        };
+        Some(Move {
+            from: ((mov & 7) as i8, (mov >> 3 & 7) as i8),
+            to: ((mov >> 6 & 7) as i8, (mov >> 9 & 7) as i8),
+            promotion,
+        })
+    };
+    Some(Entry {
+        best_move,
+        score: (data >> 16) as u32 as Score,
+        depth: (data >> 48 & 0xff) as usize,
+        bound,
+    })
+}
+
+fn generation_of(data: u64) -> u8 {
+    (data >> 58) as u8
+}
+
+/// All figures in the order of their discriminants
+const FIGURES: [Figure; 6] = [
+    Figure::Pawn,
+    Figure::Bishop,
+    Figure::Knight,
+    Figure::Rook,
+    Figure::Queen,
+    Figure::King,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::mated_in;
+
+    #[test]
+    fn store_and_probe() {
+        let table = TranspositionTable::new(1);
+        let entry = Entry {
+            best_move: Some(Move {
+                promotion: Some(Figure::Knight),
+                ..Move::new((1, 1), (0, 0))
+            }),
+            score: -123,
+            depth: 7,
+            bound: Bound::Lower,
+        };
+        assert_eq!(table.probe(42, 0), None);
+        table.store(42, 3, entry);
+        assert_eq!(table.probe(42, 5), Some(entry));
+        // Another position in the same slot
+        let other = 42 + table.slots.len() as u64;
+        assert_eq!(table.probe(other, 0), None);
+        // Deeper entries of the same search are kept
+        let shallow = Entry {
+            best_move: None,
+            depth: 2,
+            ..entry
+        };
+        table.store(other, 0, shallow);
+        assert_eq!(table.probe(42, 0), Some(entry));
+        table.new_search();
+        table.store(other, 0, shallow);
+        assert_eq!(table.probe(other, 0), Some(shallow));
+        table.clear();
+        assert_eq!(table.probe(other, 0), None);
+        assert_eq!(table.hashfull(), 0);
+    }
+
+    #[test]
+    fn generations_wrap_around() {
+        let table = TranspositionTable::new(1);
+        let deep = Entry {
+            best_move: None,
+            score: 10,
+            depth: 9,
+            bound: Bound::Lower,
+        };
+        // More searches than entries can tell apart
+        for _ in 0..100 {
+            table.new_search();
+        }
+        table.store(42, 0, deep);
+        let other = 42 + table.slots.len() as u64;
+        table.store(other, 0, Entry { depth: 1, ..deep });
+        assert_eq!(table.probe(42, 0), Some(deep));
+    }
+
+    #[test]
+    fn mate_scores_count_from_the_root() {
+        let table = TranspositionTable::new(1);
+        // Mated 5 plies from the root, found 3 plies from the root
+        let entry = Entry {
+            best_move: None,
+            score: mated_in(5),
+            depth: 2,
+            bound: Bound::Exact,
+        };
+        table.store(7, 3, entry);
+        // Reached 1 ply from the root, it is a mate in 3 plies
+        assert_eq!(table.probe(7, 1).unwrap().score, mated_in(3));
+        table.store(8, 0, Entry { score: 55, ..entry });
+        assert_eq!(table.probe(8, 4).unwrap().score, 55);
+    }
+}
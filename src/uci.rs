@@ -0,0 +1,456 @@
+//! The Universal Chess Interface, spoken by most chess GUIs and tournament
+//! managers
+
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    ai::{self, SearchHandle, SearchOptions},
+    board::Score,
+    eval,
+    fen::START_FEN,
+    figures::Color,
+    limits::{SearchLimits, MAX_DEPTH},
+    pawns::PawnTable,
+    position::Position,
+    score::Eval,
+    tt::{TranspositionTable, DEFAULT_SIZE_MB},
+};
+
+/// The search depth used if `go` doesn't give any limit
+pub const DEFAULT_DEPTH: usize = 4;
+
+/// The biggest transposition table that can be chosen, in MB
+pub const MAX_HASH_MB: usize = 4096;
+
+type Output = Arc<Mutex<dyn Write + Send>>;
+
+/// Parses the arguments of a `go` command, e.g. `wtime 60000 btime 60000`
+fn parse_go(args: &[&str], side_to_move: Color) -> SearchLimits {
+    let mut limits = SearchLimits::default();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        let millis = |args: &mut std::slice::Iter<&str>| {
+            parse_next(args).map(Duration::from_millis)
+        };
+        let own = match arg.chars().next() {
+            Some('w') => side_to_move == Color::White,
+            Some('b') => side_to_move == Color::Black,
+            _ => false,
+        };
+        match arg {
+            "depth" => limits.depth = parse_next(&mut args),
+            "movetime" => limits.movetime = millis(&mut args),
+            "wtime" | "btime" if own => limits.clock = millis(&mut args),
+            "winc" | "binc" if own => {
+                limits.increment = millis(&mut args).unwrap_or_default()
+            }
+            "movestogo" => limits.moves_to_go = parse_next(&mut args),
+            "nodes" => limits.nodes = parse_next(&mut args),
+            "infinite" => limits.infinite = true,
+            _ => {}
+        }
+    }
+    limits
+}
+
+fn parse_next<T: std::str::FromStr>(
+    args: &mut std::slice::Iter<&str>,
+) -> Option<T> {
+    args.next().and_then(|value| value.parse().ok())
+}
+
+/// A search that sends its `bestmove` when done
+struct Search {
+    handle: SearchHandle<()>,
+    infinite: bool,
+}
+
+struct Uci {
+    position: Position,
+    /// The search depth used if `go` doesn't give any limit
+    depth: usize,
+    options: SearchOptions,
+    /// Shared with the running search
+    tt: Arc<TranspositionTable>,
+    search: Option<Search>,
+    output: Output,
+}
+
+/// Answers UCI commands read from `input` until `quit` or the end of input
+pub fn run(
+    input: impl Iterator<Item = String>,
+    output: impl Write + Send + 'static,
+) {
+    let mut uci = Uci {
+        position: Position::new(),
+        depth: DEFAULT_DEPTH,
+        options: SearchOptions::default(),
+        tt: Arc::new(TranspositionTable::new(DEFAULT_SIZE_MB)),
+        search: None,
+        output: Arc::new(Mutex::new(output)),
+    };
+    for line in input {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["uci"] => {
+                uci.send("id name chess-ai");
+                uci.send(&format!(
+                    "option name Depth type spin default {} min 1 max {}",
+                    DEFAULT_DEPTH, MAX_DEPTH
+                ));
+                uci.send(&format!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_SIZE_MB, MAX_HASH_MB
+                ));
+                uci.send("option name Clear Hash type button");
+                for (name, enabled) in SearchOptions::default().switches() {
+                    uci.send(&format!(
+                        "option name {} type check default {}",
+                        name, enabled
+                    ));
+                }
+                uci.send("uciok");
+            }
+            ["isready"] => uci.send("readyok"),
+            ["ucinewgame"] => {
+                uci.stop();
+                uci.position = Position::new();
+                uci.tt.clear();
+            }
+            ["position", args @ ..] => {
+                uci.stop();
+                match parse_position(args) {
+                    Ok(position) => uci.position = position,
+                    Err(err) => uci.send(&format!("info string {}", err)),
+                }
+            }
+            ["setoption", args @ ..] => {
+                uci.stop();
+                uci.set_option(args);
+            }
+            ["go", args @ ..] => {
+                uci.stop();
+                let limits = parse_go(args, uci.position.side_to_move);
+                uci.go(limits);
+            }
+            ["stop"] => uci.stop(),
+            // Not part of UCI, but engines commonly explain their evaluation
+            ["eval"] => {
+                let trace = eval::evaluate_trace(
+                    &uci.position.board,
+                    &mut PawnTable::new(),
+                );
+                for line in trace.to_string().lines() {
+                    uci.send(&format!("info string {}", line));
+                }
+            }
+            ["quit"] => {
+                uci.stop();
+                return;
+            }
+            [] => {}
+            _ => uci.send(&format!("info string Unknown command: {}", line)),
+        }
+    }
+    // Without further input, only an infinite search has to be stopped
+    match &uci.search {
+        Some(search) if !search.infinite => uci.wait(),
+        _ => uci.stop(),
+    }
+}
+
+/// Parses the arguments of a `position` command, e.g.
+/// `startpos moves e2e4 e7e5` or `fen <fen> moves e2e4`
+pub fn parse_position(args: &[&str]) -> Result<Position, String> {
+    let moves_start = args
+        .iter()
+        .position(|&arg| arg == "moves")
+        .unwrap_or(args.len());
+    let mut position = match &args[..moves_start] {
+        ["startpos"] => Position::from_fen(START_FEN),
+        ["fen", fen @ ..] => Position::from_fen(&fen.join(" ")),
+        _ => return Err(format!("Bad position: {}", args.join(" "))),
+    }
+    .map_err(|err| format!("Bad FEN: {}", err))?;
+    for text in args.iter().skip(moves_start + 1) {
+        let mov = position.parse_uci(text).map_err(|err| err.to_string())?;
+        position.make_move(mov);
+    }
+    Ok(position)
+}
+
+impl Uci {
+    fn send(&self, line: &str) {
+        send(&self.output, line);
+    }
+
+    fn set_option(&mut self, args: &[&str]) {
+        let value_start = args
+            .iter()
+            .position(|&arg| arg == "value")
+            .unwrap_or(args.len());
+        let name = args[..value_start]
+            .iter()
+            .skip_while(|&&arg| arg == "name")
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let value = args.get(value_start + 1..).unwrap_or(&[]).join(" ");
+        match name.to_lowercase().as_str() {
+            "depth" => match value.parse() {
+                Ok(depth) if (1..=MAX_DEPTH).contains(&depth) => {
+                    self.depth = depth
+                }
+                _ => self.send(&format!("info string Bad depth: {}", value)),
+            },
+            "hash" => match value.parse() {
+                Ok(size) if (1..=MAX_HASH_MB).contains(&size) => {
+                    self.tt = Arc::new(TranspositionTable::new(size))
+                }
+                _ => {
+                    self.send(&format!("info string Bad hash size: {}", value))
+                }
+            },
+            "clear hash" => self.tt.clear(),
+            _ => match (self.options.switch(&name), value.parse()) {
+                (Some(switch), Ok(enabled)) => *switch = enabled,
+                (Some(_), _) => {
+                    self.send(&format!("info string Bad check: {}", value))
+                }
+                (None, _) => {
+                    self.send(&format!("info string Unknown option: {}", name))
+                }
+            },
+        }
+    }
+
+    fn go(&mut self, mut limits: SearchLimits) {
+        if limits.is_unlimited() && !limits.infinite {
+            limits.depth = Some(self.depth);
+        }
+        let infinite = limits.infinite;
+        let position = self.position.clone();
+        let output = Arc::clone(&self.output);
+        let options = self.options.clone();
+        let tt = Arc::clone(&self.tt);
+        let handle = SearchHandle::spawn(move |stop| {
+            think_and_send(&position, &limits, &options, &tt, stop, &output)
+        });
+        self.search = Some(Search { handle, infinite });
+    }
+
+    /// Stops the running search, if any, and waits for its `bestmove`
+    fn stop(&mut self) {
+        if let Some(search) = &self.search {
+            search.handle.stop();
+        }
+        self.wait();
+    }
+
+    /// Waits until the running search, if any, has sent its `bestmove`
+    fn wait(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.handle.join();
+        }
+    }
+}
+
+fn send(output: &Output, line: &str) {
+    let mut output = output.lock().unwrap();
+    writeln!(output, "{}", line).unwrap();
+    output.flush().unwrap();
+}
+
+/// Thinks about `position` and sends `info` lines and the `bestmove`
+fn think_and_send(
+    position: &Position,
+    limits: &SearchLimits,
+    options: &SearchOptions,
+    tt: &TranspositionTable,
+    stop: &AtomicBool,
+    output: &Output,
+) {
+    let result = ai::iterative_deepening(
+        position,
+        limits,
+        options,
+        tt,
+        stop,
+        |iteration| {
+            let millis = iteration.elapsed.as_millis() as u64;
+            let pv: Vec<String> = iteration
+                .result
+                .pv
+                .iter()
+                .map(|&mov| mov.to_uci())
+                .collect();
+            send(
+            output,
+            &format!(
+                "info depth {} score {} nodes {} nps {} hashfull {} time {} pv {}",
+                iteration.depth,
+                format_score(iteration.result.score),
+                iteration.nodes,
+                iteration.nodes * 1000 / millis.max(1),
+                tt.hashfull(),
+                millis,
+                pv.join(" ")
+            ),
+        );
+        },
+    );
+    if limits.infinite {
+        // Only answer when told to stop
+        while !stop.load(Ordering::Relaxed) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+    match result {
+        Some(result) => {
+            send(output, &format!("bestmove {}", result.best_move.to_uci()))
+        }
+        None => send(output, "bestmove 0000"),
+    }
+}
+
+/// Mates are given in moves, negative if the engine gets mated
+fn format_score(score: Score) -> String {
+    match Eval::from(score) {
+        Eval::Centipawns(centipawns) => format!("cp {}", centipawns),
+        Eval::MateIn(moves) => format!("mate {}", moves),
+        Eval::MatedIn(moves) => format!("mate -{}", moves),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Collects the output of the engine
+    #[derive(Clone, Default)]
+    pub struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Feeds `commands` to the protocol loop `run` and returns the lines it
+    /// sent
+    pub fn run_protocol(
+        run: impl FnOnce(std::vec::IntoIter<String>, Buffer),
+        commands: &[&str],
+    ) -> Vec<String> {
+        let buffer = Buffer::default();
+        let commands: Vec<String> =
+            commands.iter().map(|line| line.to_string()).collect();
+        run(commands.into_iter(), buffer.clone());
+        let output = buffer.0.lock().unwrap();
+        String::from_utf8(output.clone())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn run_commands(commands: &[&str]) -> Vec<String> {
+        run_protocol(run, commands)
+    }
+
+    #[test]
+    fn handshake() {
+        let output = run_commands(&["uci", "isready"]);
+        assert!(output[0].starts_with("id name"));
+        assert!(output.contains(&"uciok".to_string()));
+        assert_eq!(output.last().unwrap(), "readyok");
+    }
+
+    #[test]
+    fn positions() {
+        let position =
+            parse_position(&["startpos", "moves", "e2e4", "e7e5", "e1e2"])
+                .unwrap();
+        assert_eq!(
+            position.to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPPKPPP/RNBQ1BNR b kq - 1 2"
+        );
+        let fen = "4k3/8/8/8/8/8/8/4K2R w K - 0 1";
+        let args: Vec<&str> = std::iter::once("fen")
+            .chain(fen.split(' '))
+            .chain(["moves", "e1g1"])
+            .collect();
+        let position = parse_position(&args).unwrap();
+        assert_eq!(position.to_fen(), "4k3/8/8/8/8/8/8/5RK1 b - - 1 1");
+        assert!(parse_position(&["startpos", "moves", "e2e5"]).is_err());
+        assert!(parse_position(&["fen", "nonsense"]).is_err());
+    }
+
+    #[test]
+    fn go_parsing() {
+        let args = [
+            "wtime", "60000", "btime", "30000", "winc", "1000", "depth", "5",
+            "infinite",
+        ];
+        let limits = parse_go(&args, Color::White);
+        assert_eq!(limits.clock, Some(Duration::from_secs(60)));
+        assert_eq!(limits.increment, Duration::from_secs(1));
+        assert_eq!(limits.depth, Some(5));
+        assert!(limits.infinite);
+        let limits = parse_go(&args, Color::Black);
+        assert_eq!(limits.clock, Some(Duration::from_secs(30)));
+        assert_eq!(limits.increment, Duration::ZERO);
+        assert_eq!(
+            parse_go(&["movetime", "300", "nodes", "5000"], Color::Black),
+            SearchLimits {
+                movetime: Some(Duration::from_millis(300)),
+                nodes: Some(5000),
+                ..SearchLimits::default()
+            }
+        );
+    }
+
+    #[test]
+    fn finds_mate() {
+        let output = run_commands(&[
+            "position fen 6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1",
+            "go depth 2",
+        ]);
+        assert!(output[0].starts_with("info depth 1 "));
+        assert!(output[1].starts_with("info depth 2 score mate 1 "));
+        assert_eq!(output[2], "bestmove a1a8");
+    }
+
+    #[test]
+    fn eval_command() {
+        let output = run_commands(&["position startpos moves e2e4", "eval"]);
+        assert!(output[0].starts_with("info string Term "));
+        assert!(output[1].starts_with("info string Material "));
+        let position = parse_position(&["startpos", "moves", "e2e4"]).unwrap();
+        let score = eval::evaluate(&position.board, &mut PawnTable::new());
+        assert_eq!(
+            output.last().unwrap(),
+            &format!("info string Score {} (white's view)", score)
+        );
+    }
+
+    #[test]
+    fn stop_infinite_search() {
+        let output =
+            run_commands(&["position startpos", "go infinite", "stop"]);
+        let last = output.last().unwrap();
+        let mov = last.strip_prefix("bestmove ").unwrap();
+        assert!(Position::new().parse_uci(mov).is_ok());
+    }
+}
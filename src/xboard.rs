@@ -0,0 +1,453 @@
+//! The Chess Engine Communication Protocol of XBoard and WinBoard
+
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    ai::{self, SearchHandle, SearchOptions},
+    figures::Color,
+    limits::SearchLimits,
+    position::{GameStatus, Position},
+    score::Eval,
+    tt::{TranspositionTable, DEFAULT_SIZE_MB},
+    uci,
+};
+
+type Output = Arc<Mutex<dyn Write + Send>>;
+
+const FEATURES: &str = "feature myname=\"chess-ai\" ping=1 setboard=1 \
+                        usermove=1 playother=1 san=0 colors=0 sigint=0 \
+                        sigterm=0 reuse=1 analyze=0 memory=1 done=1";
+
+/// The time control set by `level` and `st`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct TimeControl {
+    /// Moves per time control period, 0 if the whole game is one period
+    moves_per_period: u32,
+    base: Duration,
+    increment: Duration,
+    /// Fixed time per move
+    per_move: Option<Duration>,
+}
+
+/// A search that plays its move when done
+struct Search {
+    handle: SearchHandle<()>,
+    /// Set to throw away the result instead of playing it
+    abort: Arc<AtomicBool>,
+}
+
+struct XBoard {
+    /// Shared with the search thread, which plays the move it finds
+    position: Arc<Mutex<Position>>,
+    /// The side played by the engine, `None` in force mode
+    engine: Option<Color>,
+    time_control: TimeControl,
+    /// The time left on the engine's clock as told by `time`
+    clock: Option<Duration>,
+    /// Maximum search depth set by `sd`
+    depth: Option<usize>,
+    /// Whether to send thinking output
+    post: bool,
+    options: SearchOptions,
+    /// Shared with the running search
+    tt: Arc<TranspositionTable>,
+    search: Option<Search>,
+    output: Output,
+}
+
+/// Answers XBoard commands read from `input` until `quit` or the end of input
+pub fn run(
+    input: impl Iterator<Item = String>,
+    output: impl Write + Send + 'static,
+) {
+    let mut xboard = XBoard {
+        position: Arc::new(Mutex::new(Position::new())),
+        engine: Some(Color::Black),
+        time_control: TimeControl::default(),
+        clock: None,
+        depth: None,
+        post: false,
+        options: SearchOptions::default(),
+        tt: Arc::new(TranspositionTable::new(DEFAULT_SIZE_MB)),
+        search: None,
+        output: Arc::new(Mutex::new(output)),
+    };
+    for line in input {
+        let (command, args) = match line.trim().split_once(' ') {
+            Some((command, args)) => (command, args.trim()),
+            None => (line.trim(), ""),
+        };
+        match command {
+            // Nothing to do for these
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy"
+            | "computer" | "name" | "rating" | "ics" | "otim" | "draw" => {}
+            "" => {}
+            "protover" => xboard.send(FEATURES),
+            "new" => {
+                xboard.abort();
+                *xboard.position.lock().unwrap() = Position::new();
+                xboard.engine = Some(Color::Black);
+                xboard.time_control = TimeControl::default();
+                xboard.clock = None;
+                xboard.depth = None;
+                xboard.tt.clear();
+            }
+            "force" => {
+                xboard.abort();
+                xboard.engine = None;
+            }
+            "go" => {
+                xboard.abort();
+                xboard.engine = Some(xboard.side_to_move());
+                xboard.think();
+            }
+            "playother" => {
+                xboard.abort();
+                xboard.engine = Some(xboard.side_to_move().flipped());
+            }
+            "usermove" => xboard.user_move(args),
+            "?" => xboard.stop(),
+            "setboard" => {
+                xboard.abort();
+                match Position::from_fen(args) {
+                    Ok(position) => *xboard.position.lock().unwrap() = position,
+                    Err(err) => xboard.send(&format!(
+                        "tellusererror Illegal position: {}",
+                        err
+                    )),
+                }
+            }
+            "level" => match parse_level(args) {
+                Some(time_control) => xboard.time_control = time_control,
+                None => xboard.error(&line, "bad time control"),
+            },
+            "st" => match args.parse::<f64>() {
+                Ok(seconds) if seconds > 0.0 => {
+                    xboard.time_control.per_move =
+                        Some(Duration::from_secs_f64(seconds))
+                }
+                _ => xboard.error(&line, "bad time per move"),
+            },
+            "sd" => match args.parse() {
+                Ok(depth) if depth > 0 => xboard.depth = Some(depth),
+                _ => xboard.error(&line, "bad depth"),
+            },
+            "time" => match args.parse::<u64>() {
+                Ok(centiseconds) => {
+                    xboard.clock =
+                        Some(Duration::from_millis(centiseconds * 10))
+                }
+                _ => xboard.error(&line, "bad time"),
+            },
+            "undo" | "remove" => {
+                xboard.abort();
+                let mut position = xboard.position.lock().unwrap();
+                for _ in 0..if command == "undo" { 1 } else { 2 } {
+                    position.unmake_move();
+                }
+            }
+            "result" => {
+                xboard.abort();
+                xboard.engine = None;
+            }
+            "memory" => match args.parse() {
+                Ok(size) if size > 0 => {
+                    xboard.abort();
+                    xboard.tt = Arc::new(TranspositionTable::new(size));
+                }
+                _ => xboard.error(&line, "bad memory size"),
+            },
+            "post" => xboard.post = true,
+            "nopost" => xboard.post = false,
+            "ping" => xboard.send(&format!("pong {}", args)),
+            "quit" => {
+                xboard.abort();
+                return;
+            }
+            _ => xboard.error(&line, "unknown command"),
+        }
+    }
+    // Without further input, let the engine finish its move
+    if let Some(search) = xboard.search.take() {
+        search.handle.join();
+    }
+}
+
+/// Parses the arguments of `level`, e.g. `40 5 0` or `0 2:30 1`
+fn parse_level(args: &str) -> Option<TimeControl> {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    if let [moves_per_period, base, increment] = args.as_slice() {
+        let base = match base.split_once(':') {
+            Some((minutes, seconds)) => {
+                minutes.parse::<u64>().ok()? * 60
+                    + seconds.parse::<u64>().ok()?
+            }
+            None => base.parse::<u64>().ok()? * 60,
+        };
+        Some(TimeControl {
+            moves_per_period: moves_per_period.parse().ok()?,
+            base: Duration::from_secs(base),
+            increment: Duration::from_secs_f64(increment.parse().ok()?),
+            per_move: None,
+        })
+    } else {
+        None
+    }
+}
+
+impl XBoard {
+    fn send(&self, line: &str) {
+        send(&self.output, line);
+    }
+
+    fn error(&self, command: &str, reason: &str) {
+        self.send(&format!("Error ({}): {}", reason, command));
+    }
+
+    fn side_to_move(&self) -> Color {
+        self.position.lock().unwrap().side_to_move
+    }
+
+    fn user_move(&mut self, text: &str) {
+        self.abort();
+        let mut position = self.position.lock().unwrap();
+        match position.parse_move(text) {
+            Ok(mov) => {
+                position.make_move(mov);
+                let engine_to_move = self.engine == Some(position.side_to_move);
+                let game_over = send_result(&position, &self.output);
+                drop(position);
+                if engine_to_move && !game_over {
+                    self.think();
+                }
+            }
+            Err(_) => self.send(&format!("Illegal move: {}", text)),
+        }
+    }
+
+    /// The limits for the engine's next move
+    fn limits(&self, fullmove_number: u32) -> SearchLimits {
+        let time_control = self.time_control;
+        let mut limits = SearchLimits {
+            depth: self.depth,
+            movetime: time_control.per_move,
+            ..SearchLimits::default()
+        };
+        if time_control.per_move.is_none()
+            && (self.clock.is_some() || time_control.base > Duration::ZERO)
+        {
+            limits.clock = Some(self.clock.unwrap_or(time_control.base));
+            limits.increment = time_control.increment;
+            if time_control.moves_per_period > 0 {
+                let period = time_control.moves_per_period;
+                limits.moves_to_go =
+                    Some(period - (fullmove_number - 1) % period);
+            }
+        }
+        if limits.is_unlimited() {
+            limits.depth = Some(uci::DEFAULT_DEPTH);
+        }
+        limits
+    }
+
+    /// Lets the engine search a move in the background and play it
+    fn think(&mut self) {
+        let abort = Arc::new(AtomicBool::new(false));
+        let mut position = self.position.lock().unwrap().clone();
+        let limits = self.limits(position.fullmove_number);
+        let shared_position = Arc::clone(&self.position);
+        let output = Arc::clone(&self.output);
+        let post = self.post;
+        let thread_abort = Arc::clone(&abort);
+        let options = self.options.clone();
+        let tt = Arc::clone(&self.tt);
+        let handle = SearchHandle::spawn(move |stop| {
+            let result = ai::iterative_deepening(
+                &position,
+                &limits,
+                &options,
+                &tt,
+                stop,
+                |iteration| {
+                    if post {
+                        send_thinking(&position, iteration, &output);
+                    }
+                },
+            );
+            let mov = match result {
+                Some(result) => result.best_move,
+                None => return,
+            };
+            // Holding the lock, so the move can't be aborted any more
+            let mut shared_position = shared_position.lock().unwrap();
+            if thread_abort.load(Ordering::Relaxed) {
+                return;
+            }
+            send(&output, &format!("move {}", mov.to_uci()));
+            position.make_move(mov);
+            send_result(&position, &output);
+            *shared_position = position;
+        });
+        self.search = Some(Search { handle, abort });
+    }
+
+    /// Makes the engine play the best move found so far
+    fn stop(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.handle.stop();
+            search.handle.join();
+        }
+    }
+
+    /// Stops the engine from thinking without playing a move
+    fn abort(&mut self) {
+        if let Some(search) = &self.search {
+            search.abort.store(true, Ordering::Relaxed);
+        }
+        self.stop();
+    }
+}
+
+fn send(output: &Output, line: &str) {
+    let mut output = output.lock().unwrap();
+    writeln!(output, "{}", line).unwrap();
+    output.flush().unwrap();
+}
+
+/// Sends a line like `9 156 1084 48000 Nf3 Nc6 Nc3 Nf6`: the depth, the score
+/// in centipawns, the time in centiseconds, the nodes and the principal
+/// variation. Mates in n moves are sent as 100000 + n, or -100000 - n for
+/// the engine getting mated
+fn send_thinking(
+    position: &Position,
+    iteration: &ai::Iteration,
+    output: &Output,
+) {
+    let mut position = position.clone();
+    let mut pv = Vec::new();
+    for &mov in &iteration.result.pv {
+        pv.push(position.to_san(mov));
+        position.make_move(mov);
+    }
+    send(
+        output,
+        &format!(
+            "{} {} {} {} {}",
+            iteration.depth,
+            match Eval::from(iteration.result.score) {
+                Eval::Centipawns(centipawns) => centipawns,
+                Eval::MateIn(moves) => 100_000 + moves as i32,
+                Eval::MatedIn(moves) => -100_000 - moves as i32,
+            },
+            iteration.elapsed.as_millis() / 10,
+            iteration.nodes,
+            pv.join(" ")
+        ),
+    );
+}
+
+/// Sends the result if the game is over and returns whether it is
+fn send_result(position: &Position, output: &Output) -> bool {
+    let status = position.status();
+    let reason = match status {
+        GameStatus::Ongoing => return false,
+        GameStatus::Checkmate { winner } => format!("{:?} mates", winner),
+        GameStatus::Stalemate => "Stalemate".to_string(),
+        GameStatus::FiftyMoveRule => "Fifty move rule".to_string(),
+        GameStatus::ThreefoldRepetition => "Threefold repetition".to_string(),
+        GameStatus::InsufficientMaterial => "Insufficient material".to_string(),
+    };
+    send(output, &format!("{} {{{}}}", status.pgn_result(), reason));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uci::tests::run_protocol;
+
+    fn run_commands(commands: &[&str]) -> Vec<String> {
+        run_protocol(run, commands)
+    }
+
+    #[test]
+    fn handshake() {
+        let output = run_commands(&["xboard", "protover 2", "ping 7"]);
+        assert_eq!(output, [FEATURES, "pong 7"]);
+    }
+
+    #[test]
+    fn plays_black() {
+        let output = run_commands(&["new", "sd 2", "usermove e2e4"]);
+        let mov = output.last().unwrap().strip_prefix("move ").unwrap();
+        let mut position = Position::new();
+        position.make_move(position.parse_uci("e2e4").unwrap());
+        assert!(position.parse_uci(mov).is_ok());
+    }
+
+    #[test]
+    fn force_mode_and_undo() {
+        let output = run_commands(&[
+            "new",
+            "force",
+            "usermove e2e4",
+            "usermove e7e5",
+            "usermove e2e4",
+            "remove",
+            "undo",
+            "usermove d2d4",
+            "setboard 6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1",
+            "sd 2",
+            "post",
+            "go",
+        ]);
+        assert_eq!(output[0], "Illegal move: e2e4");
+        assert!(output[1].starts_with("1 "));
+        assert!(output[2].starts_with("2 100001 "));
+        assert!(output[2].ends_with(" Ra8#"));
+        assert_eq!(output[3..], ["move a1a8", "1-0 {White mates}"]);
+    }
+
+    #[test]
+    fn time_controls() {
+        assert_eq!(
+            parse_level("40 2:30 1.5"),
+            Some(TimeControl {
+                moves_per_period: 40,
+                base: Duration::from_secs(150),
+                increment: Duration::from_millis(1500),
+                per_move: None,
+            })
+        );
+        assert_eq!(parse_level("0 5"), None);
+        let mut xboard = XBoard {
+            position: Arc::new(Mutex::new(Position::new())),
+            engine: Some(Color::Black),
+            time_control: parse_level("40 5 0").unwrap(),
+            clock: Some(Duration::from_secs(200)),
+            depth: None,
+            post: false,
+            options: SearchOptions::default(),
+            tt: Arc::new(TranspositionTable::new(1)),
+            search: None,
+            output: Arc::new(Mutex::new(Vec::new())),
+        };
+        let limits = xboard.limits(45);
+        assert_eq!(limits.clock, Some(Duration::from_secs(200)));
+        assert_eq!(limits.moves_to_go, Some(36));
+        xboard.time_control.per_move = Some(Duration::from_secs(3));
+        let limits = xboard.limits(45);
+        assert_eq!(limits.movetime, Some(Duration::from_secs(3)));
+        assert_eq!(limits.clock, None);
+        xboard.time_control = TimeControl::default();
+        xboard.clock = None;
+        assert_eq!(xboard.limits(45), SearchLimits::depth(uci::DEFAULT_DEPTH));
+    }
+}
@@ -0,0 +1,172 @@
+//! Zobrist hashing: Every feature of a position gets a random 64 bit key and
+//! the hash of a position is the xor of the keys of its features. A move only
+//! changes a few features, so the hash can be updated instead of recomputed
+
+use crate::{board::*, figures::*};
+
+struct Keys {
+    /// Indexed by color, figure, column and row
+    pieces: [[[[u64; 8]; 8]; 6]; 2],
+    castling: [u64; 4],
+    /// Indexed by the column of the en passant position
+    en_passant: [u64; 8],
+    black_to_move: u64,
+}
+
+/// Fixed keys, so that hashes stay the same between runs
+const KEYS: Keys = generate_keys(0x2545_f491_4f6c_dd1d);
+
+/// Fills the keys with the output of the SplitMix64 generator
+const fn generate_keys(seed: u64) -> Keys {
+    /// The `n`th output of the generator, which only depends on `n` times the
+    /// increment added to the seed
+    const fn next(seed: u64, n: usize) -> u64 {
+        let mut z = seed
+            .wrapping_add((n as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    let mut keys = Keys {
+        pieces: [[[[0; 8]; 8]; 6]; 2],
+        castling: [0; 4],
+        en_passant: [0; 8],
+        black_to_move: 0,
+    };
+    const PIECES: usize = 2 * 6 * 8 * 8;
+    let mut n = 0;
+    while n < PIECES {
+        keys.pieces[n / 384][n / 64 % 6][n / 8 % 8][n % 8] = next(seed, n);
+        n += 1;
+    }
+    while n < PIECES + 4 {
+        keys.castling[n - PIECES] = next(seed, n);
+        n += 1;
+    }
+    while n < PIECES + 4 + 8 {
+        keys.en_passant[n - PIECES - 4] = next(seed, n);
+        n += 1;
+    }
+    keys.black_to_move = next(seed, n);
+    keys
+}
+
+pub fn piece(piece: Piece, pos: Pos) -> u64 {
+    let color = match piece.color {
+        Color::White => 0,
+        Color::Black => 1,
+    };
+    KEYS.pieces[color][piece.figure as usize][pos.0 as usize][pos.1 as usize]
+}
+
+pub fn castling(rights: CastlingRights) -> u64 {
+    [
+        rights.white_king_side,
+        rights.white_queen_side,
+        rights.black_king_side,
+        rights.black_queen_side,
+    ]
+    .iter()
+    .zip(KEYS.castling)
+    .filter(|&(&allowed, _)| allowed)
+    .fold(0, |hash, (_, key)| hash ^ key)
+}
+
+/// Only the column matters, the row follows from the side to move
+pub fn en_passant(en_passant: Option<Pos>) -> u64 {
+    en_passant.map_or(0, |pos| KEYS.en_passant[pos.0 as usize])
+}
+
+pub fn side_to_move(color: Color) -> u64 {
+    match color {
+        Color::White => 0,
+        Color::Black => KEYS.black_to_move,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Position;
+
+    #[test]
+    fn keys_are_distinct() {
+        let mut keys: Vec<u64> = KEYS
+            .pieces
+            .iter()
+            .flatten()
+            .flatten()
+            .flatten()
+            .chain(&KEYS.castling)
+            .chain(&KEYS.en_passant)
+            .copied()
+            .collect();
+        keys.push(KEYS.black_to_move);
+        let count = keys.len();
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), count);
+        assert!(!keys.contains(&0));
+    }
+
+    #[test]
+    fn incremental_updates() {
+        let fen =
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut position = Position::from_fen(fen).unwrap();
+        let start = position.hash();
+        // Castling, captures, double steps, en passant and promotions
+        for uci in [
+            "e1g1", "h3g2", "a2a4", "b4a3", "e5f7", "g2f1q", "g1f1", "e8g8",
+        ] {
+            let mov = position.parse_uci(uci).unwrap();
+            position.make_move(mov);
+            assert_eq!(position.board.hash, position.board.compute_hash());
+            assert_eq!(
+                position.board.pawn_hash,
+                position.board.compute_pawn_hash()
+            );
+            let fen = position.to_fen();
+            assert_eq!(
+                position.hash(),
+                Position::from_fen(&fen).unwrap().hash()
+            );
+        }
+        while position.unmake_move().is_some() {}
+        assert_eq!(position.hash(), start);
+    }
+
+    #[test]
+    fn features_change_the_hash() {
+        let hash = |fen| Position::from_fen(fen).unwrap().hash();
+        let start =
+            hash("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w Kkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPN/RNBQKB1R w KQkq - 0 1",
+        ] {
+            assert_ne!(hash(fen), start, "{}", fen);
+        }
+        // The clocks aren't part of the position
+        assert_eq!(
+            hash("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 7 9"),
+            start
+        );
+        // En passant only counts if a pawn could hit
+        assert_eq!(
+            hash(
+                "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2"
+            ),
+            hash(
+                "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2"
+            ),
+        );
+        assert_ne!(
+            hash(
+                "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3"
+            ),
+            hash("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3"),
+        );
+    }
+}